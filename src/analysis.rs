@@ -0,0 +1,314 @@
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use colors_transform::{Color, Hsl, Rgb};
+use image::{Rgba, RgbaImage};
+use imageproc::drawing::{Blend, draw_filled_circle_mut, draw_filled_rect_mut, draw_line_segment_mut};
+use imageproc::rect::Rect;
+
+use crate::{Pendulum, Settings};
+use crate::chain::Chain;
+use crate::compile_pngs::{BACKGROUND, IMAGE_SIDE_LENGTH};
+use crate::simulation::{Stepper, swinging, wrap_angle};
+
+// Initial separation of each shadow from its pendulum, in radians of the first arm.
+const SHADOW_OFFSET: f64 = 1e-8;
+
+// Files written once the run is over, each skipped when no path was given.
+#[derive(Default)]
+pub(crate) struct Exports {
+    // Time to first flip of each pendulum as a table.
+    pub(crate) flip_csv: Option<String>,
+    // The same over the parameter grid as an image.
+    pub(crate) flip_png: Option<String>,
+    // Largest Lyapunov exponent of each pendulum as a table. Giving it turns on the shadows.
+    pub(crate) lyapunov_csv: Option<String>,
+    // Poincare section of every pendulum as an image, and the arm whose crossings it records.
+    pub(crate) poincare_png: Option<String>,
+    pub(crate) section: Section,
+}
+
+// The Poincare section is taken as one arm passes straight down with its angle increasing,
+// recording the other arm's angle and angular velocity.
+#[derive(Clone, Copy, Default)]
+pub(crate) enum Section {
+    // Crossings of the first arm, recording `(a2, a2_v)`.
+    A1,
+    // Crossings of the second arm, recording `(a1, a1_v)`.
+    #[default]
+    A2,
+}
+
+// Analyses that follow the ensemble as it runs, and the exports written at the end.
+pub(crate) struct Analysis {
+    exports: Exports,
+    lyapunov: Option<Lyapunov>,
+    poincare: Option<Poincare>,
+}
+
+// Points of the Poincare section so far, and each pendulum's arms at the end of the last tick as
+// `[a1, a2, a1_v, a2_v]`, pendulums then chains.
+struct Poincare {
+    section: Section,
+    last: Vec<[f64; 4]>,
+    points: Vec<(f64, f64, Rgb)>,
+}
+
+// `[a1, a2, a1_v, a2_v]` of the first two arms, or `None` for a chain with one link.
+fn arms(pends: &[Pendulum], chains: &[Chain]) -> Vec<Option<[f64; 4]>> {
+    swinging(pends, chains).map(|s| s.first_arms()).collect()
+}
+
+
+// Largest Lyapunov exponent by the shadow method: every pendulum has a copy started
+// `SHADOW_OFFSET` away, which is pulled back to that distance after each tick. The exponent is the
+// mean log growth of the separation per unit of simulated time.
+struct Lyapunov {
+    pends: Vec<Pendulum>,
+    chains: Vec<Chain>,
+    // Sum of the log growth of each pendulum's separation, then each chain's.
+    growth: Vec<f64>,
+    elapsed: f64,
+    stepper: Stepper,
+}
+
+// Coordinates the separation from a shadow is measured in. For pendulums these include the
+// elastic model's arm lengths, which never move in the other models.
+trait Phase {
+    fn phase(&self) -> Vec<f64>;
+    fn set_phase(&mut self, x: &[f64]);
+}
+
+impl Phase for Pendulum {
+    fn phase(&self) -> Vec<f64> {
+        vec![self.a1, self.a2, self.a1_v, self.a2_v, self.l1, self.l2, self.l1_v, self.l2_v]
+    }
+
+    fn set_phase(&mut self, x: &[f64]) {
+        [self.a1, self.a2, self.a1_v, self.a2_v, self.l1, self.l2, self.l1_v, self.l2_v] = x.try_into().unwrap();
+    }
+}
+
+impl Phase for Chain {
+    fn phase(&self) -> Vec<f64> {
+        [self.angles.as_slice(), self.velocities.as_slice()].concat()
+    }
+
+    fn set_phase(&mut self, x: &[f64]) {
+        let (q, w) = x.split_at(self.links());
+        self.angles.copy_from_slice(q);
+        self.velocities.copy_from_slice(w);
+    }
+}
+
+// Log growth of the shadow's separation over the last tick, pulling it back to `SHADOW_OFFSET`
+// along the same direction.
+fn renormalise<T: Phase>(reference: &T, shadow: &mut T) -> f64 {
+    let x = reference.phase();
+    let mut y = shadow.phase();
+    let distance = x.iter().zip(&y).map(|(a, b)| (b - a).powi(2)).sum::<f64>().sqrt();
+    if distance == 0.0 || !distance.is_finite() {
+        return 0.0;
+    }
+    for (a, b) in x.iter().zip(y.iter_mut()) {
+        *b = a + (*b - a) * SHADOW_OFFSET / distance;
+    }
+    shadow.set_phase(&y);
+    (distance / SHADOW_OFFSET).ln()
+}
+
+impl Lyapunov {
+    fn new(pends: &[Pendulum], chains: &[Chain], settings: &Settings) -> Lyapunov {
+        let mut shadow_pends = pends.to_vec();
+        for pend in shadow_pends.iter_mut() {
+            pend.a1 += SHADOW_OFFSET;
+        }
+        let mut shadow_chains = chains.to_vec();
+        for chain in shadow_chains.iter_mut() {
+            chain.angles[0] += SHADOW_OFFSET;
+        }
+        Lyapunov {
+            pends: shadow_pends,
+            chains: shadow_chains,
+            growth: vec![0.0; pends.len() + chains.len()],
+            elapsed: 0.0,
+            stepper: Stepper::new(pends, settings),
+        }
+    }
+
+    fn tick(&mut self, pends: &[Pendulum], chains: &[Chain], settings: &Settings) {
+        self.stepper.tick(&mut self.pends, &mut self.chains, settings);
+        self.stepper.sync(&mut self.pends);
+        let pairs = pends.iter().zip(self.pends.iter_mut());
+        for (growth, (pend, shadow)) in self.growth.iter_mut().zip(pairs) {
+            *growth += renormalise(pend, shadow);
+        }
+        let pairs = chains.iter().zip(self.chains.iter_mut());
+        for (growth, (chain, shadow)) in self.growth[pends.len()..].iter_mut().zip(pairs) {
+            *growth += renormalise(chain, shadow);
+        }
+        self.stepper.reload();
+        self.elapsed += settings.speed;
+    }
+
+    // Current estimate for each pendulum, then each chain.
+    fn exponents(&self) -> Vec<f64> {
+        self.growth.iter().map(|g| if self.elapsed > 0.0 { g / self.elapsed } else { 0.0 }).collect()
+    }
+}
+
+impl Poincare {
+    fn new(section: Section, pends: &[Pendulum], chains: &[Chain]) -> Poincare {
+        let last = arms(pends, chains).into_iter().map(|a| a.unwrap_or([0.0; 4])).collect();
+        Poincare { section, last, points: Vec::new() }
+    }
+
+    // Record the crossings during the last tick, interpolating linearly between its ends.
+    fn tick(&mut self, pends: &[Pendulum], chains: &[Chain]) {
+        let colors = swinging(pends, chains).map(|s| s.color());
+        for ((arms, last), color) in arms(pends, chains).into_iter().zip(self.last.iter_mut()).zip(colors) {
+            let Some(arms) = arms else { continue };
+            // Index of the arm crossing the section and of the arm being recorded.
+            let (cut, other) = match self.section {
+                Section::A1 => (0, 1),
+                Section::A2 => (1, 0),
+            };
+            let (before, after) = (wrap_angle(last[cut]), wrap_angle(arms[cut]));
+            // Going from below zero to above it, and not round the back through straight up.
+            if before < 0.0 && after >= 0.0 && after - before < PI && arms[cut + 2] > 0.0 {
+                let f = -before / (after - before);
+                let angle = last[other] + f * (arms[other] - last[other]);
+                let velocity = last[other + 2] + f * (arms[other + 2] - last[other + 2]);
+                self.points.push((wrap_angle(angle), velocity, color));
+            }
+            *last = arms;
+        }
+    }
+
+    // The section with the angle across from -PI to PI and the angular velocity up the image,
+    // scaled to fit the fastest point. Each point is coloured like its pendulum.
+    fn image(&self, settings: &Settings) -> RgbaImage {
+        let side = IMAGE_SIDE_LENGTH as f64;
+        let mut image = Blend(RgbaImage::new(IMAGE_SIDE_LENGTH, IMAGE_SIDE_LENGTH));
+        draw_filled_rect_mut(&mut image, Rect::at(0, 0).of_size(IMAGE_SIDE_LENGTH, IMAGE_SIDE_LENGTH), BACKGROUND);
+        let axes = Rgba([128, 128, 128, 255]);
+        draw_line_segment_mut(&mut image, (0.0, side as f32 / 2.0), (side as f32, side as f32 / 2.0), axes);
+        draw_line_segment_mut(&mut image, (side as f32 / 2.0, 0.0), (side as f32 / 2.0, side as f32), axes);
+
+        let fastest = self.points.iter().map(|p| p.1.abs()).fold(0.0f64, f64::max);
+        let scale = if fastest > 0.0 { 0.45 * side / fastest } else { 0.0 };
+        for (angle, velocity, color) in &self.points {
+            let x = side / 2.0 + angle / PI * side / 2.0;
+            let y = side / 2.0 - velocity * scale;
+            let color = Rgba([color.get_red() as u8, color.get_blue() as u8, color.get_green() as u8, (settings.pend_transp * 255.0f64).max(64.0) as u8]);
+            draw_filled_circle_mut(&mut image, (x as i32, y as i32), 1, color);
+        }
+        image.0
+    }
+}
+
+impl Analysis {
+    pub(crate) fn new(exports: Exports, pends: &[Pendulum], chains: &[Chain], settings: &Settings) -> Analysis {
+        let lyapunov = exports.lyapunov_csv.as_ref().map(|_| Lyapunov::new(pends, chains, settings));
+        let poincare = exports.poincare_png.as_ref().map(|_| Poincare::new(exports.section, pends, chains));
+        Analysis { exports, lyapunov, poincare }
+    }
+
+    // Whether anything follows the ensemble tick by tick, so it needs the pendulums up to date.
+    pub(crate) fn active(&self) -> bool {
+        self.lyapunov.is_some() || self.poincare.is_some()
+    }
+
+    // Follow the ensemble through a tick it has just taken.
+    pub(crate) fn tick(&mut self, pends: &[Pendulum], chains: &[Chain], settings: &Settings) {
+        if let Some(lyapunov) = &mut self.lyapunov {
+            lyapunov.tick(pends, chains, settings);
+        }
+        if let Some(poincare) = &mut self.poincare {
+            poincare.tick(pends, chains);
+        }
+    }
+
+    // Print a summary and write the exports at the end of a run.
+    pub(crate) fn finish(&self, pends: &[Pendulum], chains: &[Chain], settings: &Settings) {
+        let records = records(pends, chains);
+        if let Some(path) = &self.exports.flip_csv {
+            write_flip_csv(path, &records, settings);
+            println!("Saved flip times to {}", path);
+        }
+        if let Some(path) = &self.exports.flip_png {
+            flip_image(&records, settings).save(path).unwrap();
+            println!("Saved flip time map to {}", path);
+        }
+        if let (Some(path), Some(lyapunov)) = (&self.exports.lyapunov_csv, &self.lyapunov) {
+            let exponents = lyapunov.exponents();
+            let min = exponents.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = exponents.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            let mean = exponents.iter().sum::<f64>() / exponents.len().max(1) as f64;
+            println!("Lyapunov exponent: min {:.3e}, mean {:.3e}, max {:.3e}", min, mean, max);
+            write_csv(path, "lyapunov", &records, &exponents, settings);
+            println!("Saved Lyapunov exponents to {}", path);
+        }
+        if let (Some(path), Some(poincare)) = (&self.exports.poincare_png, &self.poincare) {
+            poincare.image(settings).save(path).unwrap();
+            println!("Saved {} Poincare section points to {}", poincare.points.len(), path);
+        }
+    }
+}
+
+// What the exports need from a pendulum or chain.
+struct Record {
+    cell: (usize, usize),
+    sweep: [f64; 2],
+    flip_time: Option<f64>,
+}
+
+fn records(pends: &[Pendulum], chains: &[Chain]) -> Vec<Record> {
+    swinging(pends, chains).map(|s| Record { cell: s.cell(), sweep: s.sweep(), flip_time: s.flip_time() }).collect()
+}
+
+// Time to first flip of each pendulum, empty if it never flipped.
+fn write_flip_csv(path: &str, records: &[Record], settings: &Settings) {
+    let values: Vec<Option<f64>> = records.iter().map(|r| r.flip_time).collect();
+    write_csv(path, "flip_time", records, &values, settings);
+}
+
+// One row per pendulum: its cell, the swept values and `values`, with `None` left empty.
+fn write_csv<V: Copy + Into<Option<f64>>>(path: &str, header: &str, records: &[Record], values: &[V], settings: &Settings) {
+    let mut file = BufWriter::new(File::create(path).unwrap());
+    write!(file, "column,row,{}", settings.varying.name()).unwrap();
+    if let Some(varying2) = settings.varying2 {
+        write!(file, ",{}", varying2.name()).unwrap();
+    }
+    writeln!(file, ",{}", header).unwrap();
+
+    for (record, value) in records.iter().zip(values) {
+        write!(file, "{},{},{}", record.cell.0, record.cell.1, record.sweep[0]).unwrap();
+        if settings.varying2.is_some() {
+            write!(file, ",{}", record.sweep[1]).unwrap();
+        }
+        match (*value).into() {
+            Some(value) => writeln!(file, ",{}", value).unwrap(),
+            None => writeln!(file, ",").unwrap(),
+        }
+    }
+    file.flush().unwrap();
+}
+
+// The time to first flip on a log scale, from red for the earliest to magenta for the latest
+// flip in the run. Pendulums that never flipped are black.
+fn flip_image(records: &[Record], settings: &Settings) -> RgbaImage {
+    let (columns, rows) = settings.grid;
+    let latest = records.iter().filter_map(|r| r.flip_time).fold(0.0f64, f64::max);
+    let mut image = RgbaImage::from_pixel(columns as u32, rows as u32, Rgba([0, 0, 0, 255]));
+    for record in records {
+        if let Some(t) = record.flip_time {
+            let share = if latest > 0.0 { t.ln_1p() / latest.ln_1p() } else { 0.0 };
+            let rgb = Hsl::from(300.0 * share as f32, 100.0, 50.0).to_rgb();
+            let pixel = Rgba([rgb.get_red() as u8, rgb.get_green() as u8, rgb.get_blue() as u8, 255]);
+            image.put_pixel(record.cell.0 as u32, record.cell.1 as u32, pixel);
+        }
+    }
+    image
+}
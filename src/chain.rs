@@ -0,0 +1,365 @@
+use colors_transform::Rgb;
+
+use crate::{Pendulum, Settings};
+use crate::integrators::{Dynamics, Hamiltonian, StepControl};
+use crate::simulation::{Swinging, effective_gravity, flip_time, phase_point, pivot_motion};
+
+// An N-link pendulum of point masses on massless rods, solved with the general mass-matrix
+// equations M(q) q'' = f(q, q'). Link 0 hangs from the pivot. Chains always use this exact
+// point-mass model, whatever `Settings::model` says.
+#[derive(Clone)]
+pub(crate) struct Chain {
+    pub(crate) lengths: Vec<f64>,
+    pub(crate) masses: Vec<f64>,
+    pub(crate) angles: Vec<f64>,
+    pub(crate) velocities: Vec<f64>,
+    // Gravity for this chain, see `Pendulum::g`.
+    pub(crate) g: f64,
+    // Column and row in the parameter grid and the swept values there, see `Pendulum::cell`.
+    pub(crate) cell: (usize, usize),
+    pub(crate) sweep: [f64; 2],
+    pub(crate) color: Rgb,
+    // Simulated time at which the outer link first swung past straight up, see
+    // `Pendulum::flip_time`.
+    pub(crate) flip_time: Option<f64>,
+    control: StepControl,
+    // Total energy before the first step.
+    e0: Option<f64>,
+    // Simulated time.
+    t: f64,
+}
+
+// Solve `m * x = b` in place for a symmetric positive definite `n` x `n` matrix (row major), by
+// Cholesky decomposition. `m` is overwritten with the factor and `b` with the solution.
+fn solve_spd(m: &mut [f64], b: &mut [f64], n: usize) {
+    for j in 0..n {
+        let mut d = m[j * n + j];
+        for k in 0..j {
+            d -= m[j * n + k] * m[j * n + k];
+        }
+        let d = d.sqrt();
+        m[j * n + j] = d;
+        for i in j + 1..n {
+            let mut s = m[i * n + j];
+            for k in 0..j {
+                s -= m[i * n + k] * m[j * n + k];
+            }
+            m[i * n + j] = s / d;
+        }
+    }
+    for i in 0..n {
+        let mut s = b[i];
+        for k in 0..i {
+            s -= m[i * n + k] * b[k];
+        }
+        b[i] = s / m[i * n + i];
+    }
+    for i in (0..n).rev() {
+        let mut s = b[i];
+        for k in i + 1..n {
+            s -= m[k * n + i] * b[k];
+        }
+        b[i] = s / m[i * n + i];
+    }
+}
+
+impl Chain {
+    pub(crate) fn new(lengths: Vec<f64>, masses: Vec<f64>, angles: Vec<f64>, g: f64, color: Rgb) -> Chain {
+        let links = lengths.len();
+        Chain {
+            lengths,
+            masses,
+            angles,
+            velocities: vec![0.0; links],
+            g,
+            cell: (0, 0),
+            sweep: [0.0; 2],
+            color,
+            flip_time: None,
+            control: StepControl::default(),
+            e0: None,
+            t: 0.0,
+        }
+    }
+
+    // A chain continuing `pend`: its two arms become the first two links (so the sweeps still
+    // apply) and any further links take their length and mass from `lengths` and `masses`, and
+    // the second arm's angle and angular velocity.
+    pub(crate) fn from_pendulum(pend: &Pendulum, lengths: &[f64], masses: &[f64]) -> Chain {
+        let links = lengths.len();
+        let mut chain_lengths = lengths.to_vec();
+        let mut chain_masses = masses.to_vec();
+        let mut angles = vec![pend.a2; links];
+        let mut velocities = vec![pend.a2_v; links];
+        let arms = [(pend.r1, pend.m1, pend.a1, pend.a1_v), (pend.r2, pend.m2, pend.a2, pend.a2_v)];
+        for (i, (r, m, a, v)) in arms.into_iter().take(links).enumerate() {
+            chain_lengths[i] = r;
+            chain_masses[i] = m;
+            angles[i] = a;
+            velocities[i] = v;
+        }
+        let mut chain = Chain::new(chain_lengths, chain_masses, angles, pend.g, pend.color);
+        chain.velocities = velocities;
+        chain.cell = pend.cell;
+        chain.sweep = pend.sweep;
+        chain
+    }
+
+    // `[angles, velocities]`
+    fn state(&self) -> Vec<f64> {
+        [self.angles.as_slice(), self.velocities.as_slice()].concat()
+    }
+
+    fn set_state(&mut self, y: &[f64]) {
+        let (q, w) = y.split_at(self.links());
+        self.angles.copy_from_slice(q);
+        self.velocities.copy_from_slice(w);
+    }
+
+    pub(crate) fn links(&self) -> usize {
+        self.lengths.len()
+    }
+
+    // Mass hanging from link `i` and everything below it.
+    fn mass_below(&self, i: usize) -> f64 {
+        self.masses[i..].iter().sum()
+    }
+
+    // Mass matrix M_ij = mu_ij l_i l_j cos(q_i - q_j), where mu_ij is the mass below the lower of
+    // the two links.
+    fn mass_matrix(&self, q: &[f64]) -> Vec<f64> {
+        let n = self.links();
+        let mut m = vec![0.0; n * n];
+        for i in 0..n {
+            for j in 0..n {
+                m[i * n + j] = self.mass_below(i.max(j)) * self.lengths[i] * self.lengths[j] * (q[i] - q[j]).cos();
+            }
+        }
+        m
+    }
+
+    // Angular accelerations for the state `[q, q_v]` at time `t`.
+    fn accelerations(&self, settings: &Settings, t: f64, y: &[f64]) -> Vec<f64> {
+        let n = self.links();
+        let (q, w) = y.split_at(n);
+        let (g, tilt) = effective_gravity(settings, t);
+
+        // f_i = -sum_j mu_ij l_i l_j sin(q_i - q_j) w_j^2 - mu_ii g l_i sin(q_i) + Q_i
+        let mut f = self.friction_forces(settings, t, y);
+        for i in 0..n {
+            for j in 0..n {
+                let mu = self.mass_below(i.max(j));
+                f[i] -= mu * self.lengths[i] * self.lengths[j] * (q[i] - q[j]).sin() * w[j] * w[j];
+            }
+            f[i] -= self.mass_below(i) * g * self.lengths[i] * (q[i] - tilt).sin();
+        }
+
+        let mut m = self.mass_matrix(q);
+        solve_spd(&mut m, &mut f, n);
+        f
+    }
+
+    // Generalised forces from viscous friction at the joints and quadratic air drag on the bobs.
+    // The pivot uses `damping1` and every joint below it `damping2`.
+    fn friction_forces(&self, settings: &Settings, t: f64, y: &[f64]) -> Vec<f64> {
+        let n = self.links();
+        let (q, w) = y.split_at(n);
+        let mut forces = vec![0.0; n];
+
+        forces[0] -= settings.damping1 * w[0];
+        for i in 1..n {
+            let torque = settings.damping2 * (w[i] - w[i - 1]);
+            forces[i] -= torque;
+            forces[i - 1] += torque;
+        }
+
+        if settings.drag != 0.0 {
+            // Walk down the chain accumulating each bob's velocity, then apply the drag on bob k
+            // to every link above it.
+            let (_, (mut vx, mut vy), _) = pivot_motion(settings, t);
+            for k in 0..n {
+                vx += self.lengths[k] * q[k].cos() * w[k];
+                vy -= self.lengths[k] * q[k].sin() * w[k];
+                let s = -settings.drag * vx.hypot(vy);
+                for j in 0..=k {
+                    forces[j] += s * (vx * self.lengths[j] * q[j].cos() - vy * self.lengths[j] * q[j].sin());
+                }
+            }
+        }
+        forces
+    }
+
+    // Current position of the pivot, before magnification.
+    pub(crate) fn pivot(&self, settings: &Settings) -> (f64, f64) {
+        pivot_motion(settings, self.t).0
+    }
+
+    // Positions of the bobs relative to the pivot, before magnification.
+    pub(crate) fn positions(&self) -> Vec<(f64, f64)> {
+        let (mut x, mut y) = (0.0, 0.0);
+        self.lengths
+            .iter()
+            .zip(&self.angles)
+            .map(|(l, a)| {
+                x += l * a.sin();
+                y += l * a.cos();
+                (x, y)
+            })
+            .collect()
+    }
+}
+
+impl Swinging for Chain {
+    fn step(&mut self, settings: &Settings) {
+        let settings = &settings.with_gravity(self.g);
+        if self.e0.is_none() {
+            self.e0 = Some(self.total_energy(settings));
+        }
+        let span = settings.speed / settings.substeps.max(1) as f64;
+        for _ in 0..settings.substeps.max(1) {
+            let mut control = self.control;
+            let outer = self.angles[self.links() - 1];
+            let y = settings.integrator.advance(self, settings, self.t, &self.state(), span, &mut control);
+            self.set_state(&y);
+            self.control = control;
+            if self.flip_time.is_none() {
+                self.flip_time = flip_time(outer, self.angles[self.links() - 1], self.t, span);
+            }
+            self.t += span;
+        }
+    }
+
+    // Kinetic and potential energy. The potential is zero with every link horizontal.
+    fn energies(&self, settings: &Settings) -> (f64, f64) {
+        let settings = &settings.with_gravity(self.g);
+        let n = self.links();
+        let m = self.mass_matrix(&self.angles);
+        let mut kinetic = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                kinetic += 0.5 * self.velocities[i] * m[i * n + j] * self.velocities[j];
+            }
+        }
+        let potential: f64 = self.positions().iter().zip(&self.masses).map(|((_, y), m)| -settings.g * m * y).sum();
+        (kinetic, potential)
+    }
+
+    // Depth of the potential well below the zero of `energies`, with every link hanging down.
+    fn potential_depth(&self, _settings: &Settings) -> f64 {
+        (0..self.links()).map(|i| self.g * self.mass_below(i) * self.lengths[i]).sum()
+    }
+
+    fn initial_energy(&self) -> Option<f64> {
+        self.e0
+    }
+
+    fn control(&self) -> StepControl {
+        self.control
+    }
+
+    fn color(&self) -> Rgb {
+        self.color
+    }
+
+    fn cell(&self) -> (usize, usize) {
+        self.cell
+    }
+
+    fn sweep(&self) -> [f64; 2] {
+        self.sweep
+    }
+
+    fn flip_time(&self) -> Option<f64> {
+        self.flip_time
+    }
+
+    fn first_arms(&self) -> Option<[f64; 4]> {
+        match self.links() {
+            1 => None,
+            _ => Some([self.angles[0], self.angles[1], self.velocities[0], self.velocities[1]]),
+        }
+    }
+
+    fn outer_angle(&self) -> f64 {
+        self.angles[self.links() - 1]
+    }
+
+    // Position in the phase view from the first two links, see `simulation::phase_point`. A single
+    // link stands in for both.
+    fn phase_point(&self, settings: &Settings) -> (f64, f64) {
+        let second = 1.min(self.links() - 1);
+        let a = [self.angles[0], self.angles[second]];
+        let a_v = [self.velocities[0], self.velocities[second]];
+        phase_point(settings, a, a_v)
+    }
+}
+
+impl Dynamics for Chain {
+    type State = Vec<f64>;
+
+    fn derivative(&self, settings: &Settings, t: f64, y: &Vec<f64>) -> Vec<f64> {
+        let mut d = y[self.links()..].to_vec();
+        d.extend(self.accelerations(settings, t, y));
+        d
+    }
+}
+
+impl Hamiltonian for Chain {
+    fn to_momenta(&self, _settings: &Settings, y: &Vec<f64>) -> Vec<f64> {
+        let n = self.links();
+        let (q, w) = y.split_at(n);
+        let m = self.mass_matrix(q);
+        let mut x = q.to_vec();
+        x.extend((0..n).map(|i| (0..n).map(|j| m[i * n + j] * w[j]).sum::<f64>()));
+        x
+    }
+
+    fn to_velocities(&self, _settings: &Settings, x: &Vec<f64>) -> Vec<f64> {
+        let n = self.links();
+        let (q, p) = x.split_at(n);
+        let mut m = self.mass_matrix(q);
+        let mut w = p.to_vec();
+        solve_spd(&mut m, &mut w, n);
+        let mut y = q.to_vec();
+        y.extend(w);
+        y
+    }
+
+    // p' = M * q'' + M' * q', with M'_ij = -mu_ij l_i l_j sin(q_i - q_j) (w_i - w_j).
+    fn canonical_derivative(&self, settings: &Settings, t: f64, x: &Vec<f64>) -> Vec<f64> {
+        let n = self.links();
+        let y = self.to_velocities(settings, x);
+        let acc = self.accelerations(settings, t, &y);
+        let (q, w) = y.split_at(n);
+        let m = self.mass_matrix(q);
+
+        let mut d = w.to_vec();
+        for i in 0..n {
+            let mut p_v = 0.0;
+            for j in 0..n {
+                let mu = self.mass_below(i.max(j));
+                let m_v = -mu * self.lengths[i] * self.lengths[j] * (q[i] - q[j]).sin() * (w[i] - w[j]);
+                p_v += m[i * n + j] * acc[j] + m_v * w[j];
+            }
+            d.push(p_v);
+        }
+        d
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solve_spd_solves_a_known_system() {
+        let mut m = [4.0, 12.0, -16.0, 12.0, 37.0, -43.0, -16.0, -43.0, 98.0];
+        let mut b = [-20.0, -43.0, 192.0];
+        solve_spd(&mut m, &mut b, 3);
+        for (x, expected) in b.iter().zip([1.0, 2.0, 3.0]) {
+            assert!((x - expected).abs() < 1e-12);
+        }
+        // The lower triangle holds the Cholesky factor.
+        assert_eq!([m[0], m[3], m[4], m[6], m[7], m[8]], [2.0, 6.0, 1.0, -8.0, 5.0, 3.0]);
+    }
+}
@@ -1,49 +1,80 @@
-use std::process::Command;
-use colors_transform::{Color, Hsl};
-use image::{ImageBuffer, Rgba};
-use imageproc::drawing::{Blend, draw_antialiased_line_segment_mut, draw_filled_circle_mut, draw_filled_rect_mut, draw_hollow_circle_mut, draw_line_segment_mut};
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{SyncSender, sync_channel};
+use std::thread::{self, JoinHandle};
+use colors_transform::{Color, Hsl, Rgb};
+use image::{Pixel, Rgba, RgbaImage};
+use imageproc::drawing::{Blend, Canvas, draw_filled_circle_mut, draw_filled_rect_mut, draw_hollow_circle_mut, draw_line_segment_mut};
 use imageproc::rect::Rect;
-use crate::{Pendulum, Settings, Quality};
+use crate::{FractalColor, Pendulum, Settings, Quality, View};
+use crate::analysis::Analysis;
+use crate::chain::Chain;
+use crate::simulation::{self, Stepper};
 
-static IMAGE_SIDE_LENGTH: u32 = 1500;
+pub(crate) static IMAGE_SIDE_LENGTH: u32 = 1500;
+pub(crate) static BACKGROUND: Rgba<u8> = Rgba([51, 51, 51, 255]);
 
 
-impl Pendulum {
-    fn update_draw_img(
-        mut self,
-        settings: Settings,
-        image: &mut Blend<ImageBuffer<Rgba<u8>, Vec<u8>>>,
-    ) -> Pendulum {
-        let a1 = self.a1;
-        let a2 = self.a2;
-        let a1_v = self.a1_v;
-        let a2_v = self.a2_v;
-        let r1 = self.r1;
-        let r2 = self.r2;
-        let m1 = self.m1;
-        let m2 = self.m2;
-        let g = settings.g;
+// Rows `top..top + rows` of a frame, which one thread draws. Drawing uses whole-frame coordinates
+// and blends like `Blend`, but pixels outside the band are dropped, so the bands together come
+// out exactly as if the frame were drawn in one go.
+struct Band<'a> {
+    pixels: &'a mut [u8],
+    top: u32,
+    rows: u32,
+}
 
-        let mut num1 = -g * (2.0 * m1 + m2) * (a1);
-        let mut num2 = -m2 * g * (a1 - 2.0 * a2).sin();
-        let mut num3 = -2.0 * (a1 - a2).sin() * m2;
-        let mut num4 = a2_v * a2_v * r2 + a1_v * a1_v * r1 * (a1 - a2).cos();
-        let mut den = r1 * (2.0 * m1 + m2 - m2 * (2.0 * a1 - 2.0 * a2).cos());
-        let a1_a = (num1 + num2 + num3 * num4) / den;
+impl Band<'_> {
+    fn index(&self, x: u32, y: u32) -> Option<usize> {
+        if y < self.top || y >= self.top + self.rows {
+            return None;
+        }
+        Some(4 * ((y - self.top) * IMAGE_SIDE_LENGTH + x) as usize)
+    }
+}
 
-        num1 = 2.0 * (a1 - a2).sin();
-        num2 = a1_v * a1_v * r1 * (m1 + m2);
-        num3 = g * (m1 + m2) * (a1).cos();
-        num4 = a2_v * a2_v * r2 * m2 * (a1 - a2).cos();
-        den = r2 * (2.0 * m1 + m2 - m2 * (2.0 * a1 - 2.0 * a2).cos());
-        let a2_a = (num1 * (num2 + num3 + num4)) / den;
+impl Canvas for Band<'_> {
+    type Pixel = Rgba<u8>;
 
-        let x1 = r1 * (a1).sin();
-        let y1 = r1 * (a1).cos();
+    fn dimensions(&self) -> (u32, u32) {
+        (IMAGE_SIDE_LENGTH, IMAGE_SIDE_LENGTH)
+    }
 
-        let x2 = x1 + r2 * (a2).sin();
-        let y2 = y1 + r2 * (a2).cos();
+    fn get_pixel(&self, x: u32, y: u32) -> Rgba<u8> {
+        match self.index(x, y) {
+            Some(i) => Rgba(self.pixels[i..i + 4].try_into().unwrap()),
+            None => Rgba([0, 0, 0, 0]),
+        }
+    }
 
+    fn draw_pixel(&mut self, x: u32, y: u32, color: Rgba<u8>) {
+        if let Some(i) = self.index(x, y) {
+            let mut pix = self.get_pixel(x, y);
+            pix.blend(&color);
+            self.pixels[i..i + 4].copy_from_slice(&pix.0);
+        }
+    }
+}
+
+impl Pendulum {
+    // Rows of the frame the pendulum may cover, with room for the bobs. At medium quality a bob's
+    // circle has radius m but is centred m/2 off the bob, so it reaches 1.5m past it.
+    fn image_rows(&self, settings: &Settings) -> (f64, f64) {
+        let (_, py) = self.pivot(settings);
+        let [(_, y1), (_, y2)] = self.positions();
+        let ys = [py, py + y1, py + y2].map(|y| y * settings.mag + (IMAGE_SIDE_LENGTH / 2) as f64);
+        let margin = 1.5 * self.m1.max(self.m2) + settings.pend_width + 1.0;
+        (ys.iter().copied().fold(f64::INFINITY, f64::min) - margin, ys.iter().copied().fold(f64::NEG_INFINITY, f64::max) + margin)
+    }
+
+    fn draw_img<C: Canvas<Pixel = Rgba<u8>>>(&self, settings: &Settings, image: &mut C) {
+        let m1 = self.m1;
+        let m2 = self.m2;
+        let (px, py) = self.pivot(settings);
+        let [(x1, y1), (x2, y2)] = self.positions();
+        let (x1, y1, x2, y2) = (x1 + px, y1 + py, x2 + px, y2 + py);
 
         // let line_t = Line::new(
         //     [
@@ -60,7 +91,7 @@ impl Pendulum {
         let color = Rgba([self.color.get_red() as u8, self.color.get_blue() as u8, self.color.get_green() as u8, (settings.pend_transp * 255.0f64) as u8]);
 
         let midpt = (IMAGE_SIDE_LENGTH / 2) as f64;
-        draw_line_segment_mut(image, (midpt as f32, midpt as f32), ((x1 * mag + midpt) as f32, (y1 * mag + midpt) as f32), color);
+        draw_line_segment_mut(image, ((px * mag + midpt) as f32, (py * mag + midpt) as f32), ((x1 * mag + midpt) as f32, (y1 * mag + midpt) as f32), color);
         draw_line_segment_mut(image, ((x1 * mag + midpt) as f32, (y1 * mag + midpt) as f32), ((x2 * mag + midpt) as f32, (y2 * mag + midpt) as f32), color);
 
 
@@ -75,17 +106,243 @@ impl Pendulum {
             draw_hollow_circle_mut(image, ((x1 * mag + midpt) as i32, (y1 * mag + midpt) as i32), m1 as i32, black_transparent);
             draw_hollow_circle_mut(image, ((x2 * mag + midpt) as i32, (y2 * mag + midpt) as i32), m2 as i32, black_transparent);
         }
+    }
+}
+
+impl Chain {
+    // Rows of the frame the chain may cover, see `Pendulum::image_rows`.
+    fn image_rows(&self, settings: &Settings) -> (f64, f64) {
+        let (_, py) = self.pivot(settings);
+        let margin = self.masses.iter().copied().fold(0.0, f64::max) + settings.pend_width + 1.0;
+        let ys = self.positions().into_iter().map(|(_, y)| py + y).chain([py]);
+        let ys: Vec<f64> = ys.map(|y| y * settings.mag + (IMAGE_SIDE_LENGTH / 2) as f64).collect();
+        (ys.iter().copied().fold(f64::INFINITY, f64::min) - margin, ys.iter().copied().fold(f64::NEG_INFINITY, f64::max) + margin)
+    }
+
+    fn draw_img<C: Canvas<Pixel = Rgba<u8>>>(&self, settings: &Settings, image: &mut C) {
+        let mag = settings.mag;
+        let color = Rgba([self.color.get_red() as u8, self.color.get_blue() as u8, self.color.get_green() as u8, (settings.pend_transp * 255.0f64) as u8]);
+        let midpt = (IMAGE_SIDE_LENGTH / 2) as f64;
+
+        let (px, py) = self.pivot(settings);
+        let (mut x0, mut y0) = (px * mag + midpt, py * mag + midpt);
+        for ((x, y), m) in self.positions().into_iter().zip(&self.masses) {
+            let (x, y) = ((x + px) * mag + midpt, (y + py) * mag + midpt);
+            draw_line_segment_mut(image, (x0 as f32, y0 as f32), (x as f32, y as f32), color);
+            if settings.quality != Quality::Low {
+                draw_filled_circle_mut(image, (x as i32, y as i32), *m as i32, color);
+            }
+            (x0, y0) = (x, y);
+        }
+    }
+}
 
-        self.a1_v += a1_a * settings.speed;
-        self.a2_v += a2_a * settings.speed;
-        self.a1 += a1_v * settings.speed;
-        self.a2 += a2_v * settings.speed;
+// A pendulum's point in the phase view, see `draw_phase_point` in main.
+// Only drawn into the band holding rows `rows`.
+fn draw_phase_point_img<C: Canvas<Pixel = Rgba<u8>>>(point: (f64, f64), color: Rgb, settings: &Settings, image: &mut C, rows: (f64, f64)) {
+    let color = Rgba([color.get_red() as u8, color.get_blue() as u8, color.get_green() as u8, (settings.pend_transp * 255.0f64) as u8]);
+    let midpt = (IMAGE_SIDE_LENGTH / 2) as f64;
+    let (x, y) = (point.0 * settings.mag + midpt, point.1 * settings.mag + midpt);
+    let radius = (settings.pend_width / 2.0) as i32;
+    if y + (radius + 1) as f64 >= rows.0 && y - ((radius + 1) as f64) < rows.1 {
+        draw_filled_circle_mut(image, (x as i32, y as i32), radius, color);
+    }
+}
 
-        self
+// Draw the pendulums, or their points in the phase view, that reach rows `rows` of `image`.
+fn draw_scene<C: Canvas<Pixel = Rgba<u8>>>(pends: &[Pendulum], chains: &[Chain], settings: &Settings, image: &mut C, rows: (f64, f64)) {
+    let overlaps = |(top, bottom): (f64, f64)| bottom >= rows.0 && top < rows.1;
+    if settings.view == View::Phase {
+        for swinging in simulation::swinging(pends, chains) {
+            draw_phase_point_img(swinging.phase_point(settings), swinging.color(), settings, image, rows);
+        }
+        return;
+    }
+    for pend in pends.iter().filter(|p| overlaps(p.image_rows(settings))) {
+        pend.draw_img(settings, image);
+        // if i % 10000 == 0 {println!("Updated pendulum {}", i);}
     }
+    for chain in chains.iter().filter(|c| overlaps(c.image_rows(settings))) {
+        chain.draw_img(settings, image);
+    }
+}
+
+// A frame of the pendulums. It is split into one horizontal band per thread, each drawing every
+// pendulum that reaches it.
+fn draw_frame(pends: &[Pendulum], chains: &[Chain], settings: &Settings) -> RgbaImage {
+    let mut image = Blend(image::RgbaImage::new(IMAGE_SIDE_LENGTH, IMAGE_SIDE_LENGTH));
+    // fill the image with (0.2, 0.2, 0.2, 1.0)
+    draw_filled_rect_mut(&mut image, Rect::at(0, 0).of_size(1500, 1500), BACKGROUND);
+    let mut image = image.0;
+
+    let rows_per_band = IMAGE_SIDE_LENGTH.div_ceil(settings.threads.max(1) as u32);
+    let bands = image.chunks_mut((4 * IMAGE_SIDE_LENGTH * rows_per_band) as usize).enumerate().map(|(i, pixels)| {
+        let rows = (pixels.len() / (4 * IMAGE_SIDE_LENGTH as usize)) as u32;
+        Band { pixels, top: i as u32 * rows_per_band, rows }
+    });
+    std::thread::scope(|scope| {
+        for mut band in bands {
+            let rows = (band.top as f64, (band.top + band.rows) as f64);
+            scope.spawn(move || draw_scene(pends, chains, settings, &mut band, rows));
+        }
+    });
+    image
 }
 
-pub(crate) fn main(compile_frames: i32, mut pends: Vec<Pendulum>, amt_sep: f64, settings: Settings) {
+// Colour of one pixel of the fractal map, given the outer arm's angle, the kinetic energy and
+// potential depth, and whether the pendulum has flipped.
+fn fractal_pixel(color: FractalColor, angle: f64, kinetic: f64, depth: f64, flipped: bool) -> Rgba<u8> {
+    let hue = match color {
+        FractalColor::Angle => angle.rem_euclid(2.0 * PI).to_degrees(),
+        FractalColor::Energy => {
+            let share = if depth > 0.0 { (kinetic / depth).min(1.0) } else { 0.0 };
+            240.0 * (1.0 - share)
+        }
+        FractalColor::Flipped => return if flipped { Rgba([255, 255, 255, 255]) } else { BACKGROUND },
+    };
+    let rgb = Hsl::from(hue as f32, 100.0, 50.0).to_rgb();
+    Rgba([rgb.get_red() as u8, rgb.get_green() as u8, rgb.get_blue() as u8, 255])
+}
+
+// The parameter grid as an image, one pixel per pendulum at its cell.
+fn fractal_frame(pends: &[Pendulum], chains: &[Chain], settings: &Settings, color: FractalColor) -> RgbaImage {
+    let (columns, rows) = settings.grid;
+    let mut image = RgbaImage::from_pixel(columns as u32, rows as u32, BACKGROUND);
+    for swinging in simulation::swinging(pends, chains) {
+        let (kinetic, _) = swinging.energies(settings);
+        let depth = swinging.potential_depth(settings);
+        let pixel = fractal_pixel(color, swinging.outer_angle(), kinetic, depth, swinging.flip_time().is_some());
+        let (column, row) = swinging.cell();
+        image.put_pixel(column as u32, row as u32, pixel);
+    }
+    image
+}
+
+// How ffmpeg encodes the compiled frames.
+pub(crate) struct VideoSettings {
+    // Pipe the frames straight into ffmpeg instead of saving them as PNGs first.
+    pub(crate) stream: bool,
+    // Frames per second the frames are played at, and the video is written at.
+    pub(crate) input_fps: f64,
+    pub(crate) output_fps: f64,
+    pub(crate) codec: String,
+    // Constant rate factor, left to the codec's default when not given.
+    pub(crate) crf: Option<f64>,
+    pub(crate) pix_fmt: String,
+    pub(crate) output: String,
+    // Container to write, otherwise guessed by ffmpeg from the output's extension.
+    pub(crate) format: Option<String>,
+    // Passed to ffmpeg as is, just before the output file.
+    pub(crate) extra_args: Vec<String>,
+}
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        VideoSettings {
+            stream: false,
+            input_fps: 10.0,
+            output_fps: 60.0,
+            codec: "libx264".to_string(),
+            crf: None,
+            pix_fmt: "yuv420p".to_string(),
+            output: "output.mp4".to_string(),
+            format: None,
+            extra_args: Vec::new(),
+        }
+    }
+}
+
+impl VideoSettings {
+    // Pass the output video's arguments, after the input's, to `ffmpeg`.
+    fn output_args(&self, ffmpeg: &mut Command) {
+        ffmpeg
+            .arg("-c:v")
+            .arg(&self.codec)
+            .arg("-r")
+            .arg(self.output_fps.to_string())
+            .arg("-pix_fmt")
+            .arg(&self.pix_fmt);
+        if let Some(crf) = self.crf {
+            ffmpeg.arg("-crf").arg(crf.to_string());
+        }
+        if let Some(format) = &self.format {
+            ffmpeg.arg("-f").arg(format);
+        }
+        ffmpeg.args(&self.extra_args).arg("-y").arg(&self.output);
+    }
+}
+
+// Print whether ffmpeg made the video, with its log when it didn't.
+fn report(status: io::Result<ExitStatus>, stderr: &[u8]) {
+    match status {
+        Ok(status) if status.success() => println!("Done stitching frames!"),
+        Ok(status) => println!("ffmpeg failed ({}):\n{}", status, String::from_utf8_lossy(stderr)),
+        Err(e) => println!("Couldn't run ffmpeg: {}", e),
+    }
+}
+
+// ffmpeg encoding raw RGBA frames from its stdin as they are rendered, so no PNGs touch the disk.
+// A thread writes the frames so encoding overlaps the next frame's simulation, and another
+// collects ffmpeg's log, which it writes as it goes and would otherwise block on.
+struct Stream {
+    ffmpeg: Child,
+    frames: SyncSender<RgbaImage>,
+    writer: JoinHandle<io::Result<()>>,
+    log: JoinHandle<Vec<u8>>,
+}
+
+impl Stream {
+    fn spawn(video: &VideoSettings, (width, height): (u32, u32), backlog: usize) -> io::Result<Stream> {
+        let mut ffmpeg = Command::new("ffmpeg");
+        ffmpeg
+            .arg("-f")
+            .arg("rawvideo")
+            .arg("-pix_fmt")
+            .arg("rgba")
+            .arg("-s")
+            .arg(format!("{}x{}", width, height))
+            .arg("-framerate")
+            .arg(video.input_fps.to_string())
+            .arg("-i")
+            .arg("-");
+        video.output_args(&mut ffmpeg);
+        let mut ffmpeg = ffmpeg.stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::piped()).spawn()?;
+
+        let mut stdin = ffmpeg.stdin.take().unwrap();
+        let (frames, received) = sync_channel::<RgbaImage>(backlog);
+        let writer = thread::spawn(move || {
+            for image in received {
+                stdin.write_all(&image)?;
+            }
+            Ok(())
+        });
+        let mut stderr = ffmpeg.stderr.take().unwrap();
+        let log = thread::spawn(move || {
+            let mut log = Vec::new();
+            stderr.read_to_end(&mut log).unwrap_or_default();
+            log
+        });
+        Ok(Stream { ffmpeg, frames, writer, log })
+    }
+
+    // Queue a frame for ffmpeg, false once it has stopped taking them.
+    fn send(&self, image: RgbaImage) -> bool {
+        self.frames.send(image).is_ok()
+    }
+
+    // Close ffmpeg's input, wait for it to finish the video and report how it went.
+    fn finish(self) {
+        let Stream { mut ffmpeg, frames, writer, log } = self;
+        drop(frames);
+        if let Err(e) = writer.join().unwrap() {
+            println!("Couldn't write frames to ffmpeg: {}", e);
+        }
+        let status = ffmpeg.wait();
+        report(status, &log.join().unwrap());
+    }
+}
+
+pub(crate) fn main(compile_frames: i32, pends: &mut [Pendulum], chains: &mut [Chain], settings: Settings, analysis: &mut Analysis, video: &VideoSettings) {
     // Innit the pendulums
     // let mut pends = Vec::new();
     // for i in 0..amt_pend {
@@ -102,39 +359,108 @@ pub(crate) fn main(compile_frames: i32, mut pends: Vec<Pendulum>, amt_sep: f64,
     //             .to_rgb(),
     //     ));
     // }
-    let amt_pend = pends.len();
-    
-    // Try creating the folder /frames if it doesn't exist.
-    std::fs::create_dir("frames").unwrap_or_default();
+    let stream = if video.stream {
+        let size = match settings.fractal {
+            Some(_) => (settings.grid.0 as u32, settings.grid.1 as u32),
+            None => (IMAGE_SIDE_LENGTH, IMAGE_SIDE_LENGTH),
+        };
+        match Stream::spawn(video, size, settings.threads.max(1)) {
+            Ok(stream) => Some(stream),
+            Err(e) => {
+                println!("Couldn't run ffmpeg: {}", e);
+                return;
+            }
+        }
+    } else {
+        // Try creating the folder /frames if it doesn't exist.
+        std::fs::create_dir("frames").unwrap_or_default();
+        None
+    };
 
-    // Write the frames to a file.
+    // Write the frames to a file, or to ffmpeg. Each frame is encoded and saved on its own thread
+    // while the next one simulates, with at most one save in flight per thread.
+    let mut saving = VecDeque::new();
+    let mut stepper = Stepper::new(pends, &settings);
     for frame_i in 0..compile_frames {
-        let mut image = Blend(image::RgbaImage::new(IMAGE_SIDE_LENGTH, IMAGE_SIDE_LENGTH));
-        // fill the image with (0.2, 0.2, 0.2, 1.0)
-        draw_filled_rect_mut(&mut image, Rect::at(0, 0).of_size(1500, 1500), Rgba([51, 51, 51, 255]));
-        for i in 0..amt_pend {
-            pends[i] = pends[i].update_draw_img(settings, &mut image);
-            // if i % 10000 == 0 {println!("Updated pendulum {}", i);}
+        let image = match settings.fractal {
+            Some(color) => fractal_frame(pends, chains, &settings, color),
+            None => draw_frame(pends, chains, &settings),
+        };
+        if let Some(stream) = &stream {
+            if !stream.send(image) {
+                println!("ffmpeg stopped taking frames at frame {}", frame_i);
+                break;
+            }
+            println!("Rendered frame {}", frame_i);
+        } else {
+            if saving.len() >= settings.threads.max(1) {
+                let (i, save): (i32, JoinHandle<()>) = saving.pop_front().unwrap();
+                save.join().unwrap();
+                println!("Saved frame {}", i);
+            }
+            saving.push_back((frame_i, thread::spawn(move || image.save(format!("frames/{}.png", frame_i)).unwrap())));
         }
-        image.0.save(format!("frames/{}.png", frame_i)).unwrap();
-        println!("Saved frame {}", frame_i);
+        stepper.tick(pends, chains, &settings);
+        stepper.sync(pends);
+        analysis.tick(pends, chains, &settings);
+    }
+    for (i, save) in saving {
+        save.join().unwrap();
+        println!("Saved frame {}", i);
     }
-    println!("Done saving frames!");
+    if stream.is_none() {
+        println!("Done saving frames!");
+    }
+    simulation::print_step_stats(pends, chains, &settings);
+    if let Some(drift) = simulation::energy_drift_summary(pends, chains, &settings) {
+        println!("Energy drift after {} frames: {}", compile_frames, drift);
+    }
+    if let Some(stream) = stream {
+        stream.finish();
+        return;
+    }
+
     // Stitch the frames together into a video.
     let mut ffmpeg = Command::new("ffmpeg");
     ffmpeg
         .arg("-framerate")
-        .arg("10")
+        .arg(video.input_fps.to_string())
         .arg("-i")
-        .arg("frames/%d.png")
-        .arg("-c:v")
-        .arg("libx264")
-        .arg("-r")
-        .arg("60")
-        .arg("-pix_fmt")
-        .arg("yuv420p")
-        .arg("output.mp4");
-    ffmpeg.output().unwrap();
-    // ffmpeg -framerate 10 -i frames/%d.png -c:v libx264 -r 60 -pix_fmt yuv420p output.mp4
-    println!("Done stitching frames!");
-}
\ No newline at end of file
+        .arg("frames/%d.png");
+    video.output_args(&mut ffmpeg);
+    match ffmpeg.output() {
+        Ok(output) => report(Ok(output.status), &output.stderr),
+        Err(e) => report(Err(e), &[]),
+    }
+    // By default: ffmpeg -framerate 10 -i frames/%d.png -c:v libx264 -r 60 -pix_fmt yuv420p -y output.mp4
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw_frame_is_the_same_on_any_number_of_threads() {
+        let mut settings = Settings::new();
+        settings.pend_transp = 0.5;
+        let r = [settings.r1, settings.r2];
+        // Heavy outer bobs all the way round from straight up, so many of them straddle the edges of
+        // the four bands.
+        let pends: Vec<Pendulum> = (0..500)
+            .map(|i| {
+                let a = 2.0 * PI * i as f64 / 500.0;
+                Pendulum::new([PI, a], [0.0, 0.0], r, [10.0, 30.0], settings.g, Rgb::from((i % 256) as f32, 100.0, 255.0 - (i % 256) as f32))
+            })
+            .collect();
+        let chains: Vec<Chain> = (0..6)
+            .map(|i| Chain::new(vec![80.0; 3], vec![25.0; 3], vec![i as f64, 2.0 * i as f64, 0.5], settings.g, Rgb::from(255.0, 40.0 * i as f32, 0.0)))
+            .collect();
+        for quality in [Quality::Medium, Quality::High] {
+            settings.quality = quality;
+            settings.threads = 1;
+            let one = draw_frame(&pends, &chains, &settings);
+            settings.threads = 4;
+            let four = draw_frame(&pends, &chains, &settings);
+            assert!(one == four);
+        }
+    }
+}
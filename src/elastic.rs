@@ -0,0 +1,182 @@
+use crate::{Pendulum, Settings};
+use crate::integrators::{Dynamics, Hamiltonian};
+use crate::simulation::{effective_gravity, pivot_motion};
+
+// The elastic double pendulum: `r1` and `r2` are the springs' rest lengths and the current lengths
+// `l1`, `l2` are extra degrees of freedom. With springs for arms nothing is constrained, so the
+// bobs are moved by Newton's law in x/y and converted to polar coordinates. Its state is
+// `[a1, a2, l1, l2, a1_v, a2_v, l1_v, l2_v]`.
+pub(crate) struct Elastic<'a>(pub(crate) &'a Pendulum);
+
+type Vec2 = (f64, f64);
+
+fn dot(a: Vec2, b: Vec2) -> f64 {
+    a.0 * b.0 + a.1 * b.1
+}
+
+fn scale(s: f64, a: Vec2) -> Vec2 {
+    (s * a.0, s * a.1)
+}
+
+fn add(a: Vec2, b: Vec2) -> Vec2 {
+    (a.0 + b.0, a.1 + b.1)
+}
+
+// Radial and tangential unit vectors of an arm at angle `a` (y points down).
+fn radial(a: f64) -> Vec2 {
+    ((a).sin(), (a).cos())
+}
+
+fn tangential(a: f64) -> Vec2 {
+    ((a).cos(), -(a).sin())
+}
+
+impl Elastic<'_> {
+    pub(crate) fn state(&self) -> [f64; 8] {
+        let p = self.0;
+        [p.a1, p.a2, p.l1, p.l2, p.a1_v, p.a2_v, p.l1_v, p.l2_v]
+    }
+
+    // Velocities of the two bobs relative to the pivot.
+    fn velocities(&self, y: &[f64; 8]) -> (Vec2, Vec2) {
+        let [a1, a2, l1, l2, a1_v, a2_v, l1_v, l2_v] = *y;
+        let v1 = add(scale(l1_v, radial(a1)), scale(l1 * a1_v, tangential(a1)));
+        let v2 = add(v1, add(scale(l2_v, radial(a2)), scale(l2 * a2_v, tangential(a2))));
+        (v1, v2)
+    }
+
+    // Accelerations of the two bobs in the pivot's frame at time `t`.
+    fn accelerations(&self, settings: &Settings, t: f64, y: &[f64; 8]) -> (Vec2, Vec2) {
+        let p = self.0;
+        let [a1, a2, l1, l2, a1_v, a2_v, l1_v, l2_v] = *y;
+        let (v1, v2) = self.velocities(y);
+
+        let (g, tilt) = effective_gravity(settings, t);
+        let gravity = scale(g, radial(tilt));
+
+        // Spring tension, pulling the bobs together when positive.
+        let t1 = settings.stiffness * (l1 - p.r1) + settings.spring_damping * l1_v;
+        let t2 = settings.stiffness * (l2 - p.r2) + settings.spring_damping * l2_v;
+        let mut f1 = add(scale(-t1, radial(a1)), scale(t2, radial(a2)));
+        let mut f2 = scale(-t2, radial(a2));
+
+        // Joint friction, as torques on the arms. A torque on a massless arm is a sideways force
+        // on its outer end, balanced at its inner end.
+        let relative = a2_v - a1_v;
+        let torque1 = -settings.damping1 * a1_v + settings.damping2 * relative;
+        let torque2 = -settings.damping2 * relative;
+        f1 = add(f1, scale(torque1 / l1, tangential(a1)));
+        f1 = add(f1, scale(-torque2 / l2, tangential(a2)));
+        f2 = add(f2, scale(torque2 / l2, tangential(a2)));
+
+        if settings.drag != 0.0 {
+            let (_, pivot_v, _) = pivot_motion(settings, t);
+            let (w1, w2) = (add(v1, pivot_v), add(v2, pivot_v));
+            f1 = add(f1, scale(-settings.drag * w1.0.hypot(w1.1), w1));
+            f2 = add(f2, scale(-settings.drag * w2.0.hypot(w2.1), w2));
+        }
+
+        (add(gravity, scale(1.0 / p.m1, f1)), add(gravity, scale(1.0 / p.m2, f2)))
+    }
+
+    // Kinetic and potential energy, including the energy stored in the springs.
+    pub(crate) fn energies(&self, settings: &Settings) -> (f64, f64) {
+        let p = self.0;
+        let y = self.state();
+        let (v1, v2) = self.velocities(&y);
+        let kinetic = 0.5 * (p.m1 * dot(v1, v1) + p.m2 * dot(v2, v2));
+        let [(_, y1), (_, y2)] = p.positions();
+        let springs = 0.5 * settings.stiffness * ((p.l1 - p.r1).powi(2) + (p.l2 - p.r2).powi(2));
+        (kinetic, -settings.g * (p.m1 * y1 + p.m2 * y2) + springs)
+    }
+}
+
+impl Dynamics for Elastic<'_> {
+    type State = [f64; 8];
+
+    // Bob accelerations converted to polar form: along the arm l'' - l a'^2, across it
+    // l a'' + 2 l' a'. The second arm uses the acceleration relative to the first bob.
+    fn derivative(&self, settings: &Settings, t: f64, y: &[f64; 8]) -> [f64; 8] {
+        let [a1, a2, l1, l2, a1_v, a2_v, l1_v, l2_v] = *y;
+        let (acc1, acc2) = self.accelerations(settings, t, y);
+        let rel = add(acc2, scale(-1.0, acc1));
+        [
+            a1_v,
+            a2_v,
+            l1_v,
+            l2_v,
+            (dot(acc1, tangential(a1)) - 2.0 * l1_v * a1_v) / l1,
+            (dot(rel, tangential(a2)) - 2.0 * l2_v * a2_v) / l2,
+            dot(acc1, radial(a1)) + l1 * a1_v * a1_v,
+            dot(rel, radial(a2)) + l2 * a2_v * a2_v,
+        ]
+    }
+}
+
+// The momenta are p = sum m J^T v over the bobs, where J maps the coordinate velocities to
+// the bob's velocity. The first arm's momenta only see the combined momentum of both bobs and
+// the second arm's only the outer bob's.
+impl Hamiltonian for Elastic<'_> {
+    fn to_momenta(&self, _settings: &Settings, y: &[f64; 8]) -> [f64; 8] {
+        let p = self.0;
+        let [a1, a2, l1, l2, ..] = *y;
+        let (v1, v2) = self.velocities(y);
+        let both = add(scale(p.m1, v1), scale(p.m2, v2));
+        let outer = scale(p.m2, v2);
+        [
+            a1,
+            a2,
+            l1,
+            l2,
+            l1 * dot(both, tangential(a1)),
+            l2 * dot(outer, tangential(a2)),
+            dot(both, radial(a1)),
+            dot(outer, radial(a2)),
+        ]
+    }
+
+    fn to_velocities(&self, _settings: &Settings, x: &[f64; 8]) -> [f64; 8] {
+        let p = self.0;
+        let [a1, a2, l1, l2, p_a1, p_a2, p_l1, p_l2] = *x;
+        let both = add(scale(p_a1 / l1, tangential(a1)), scale(p_l1, radial(a1)));
+        let outer = add(scale(p_a2 / l2, tangential(a2)), scale(p_l2, radial(a2)));
+        let v2 = scale(1.0 / p.m2, outer);
+        let v1 = scale(1.0 / p.m1, add(both, scale(-1.0, outer)));
+        let rel = add(v2, scale(-1.0, v1));
+        [
+            a1,
+            a2,
+            l1,
+            l2,
+            dot(v1, tangential(a1)) / l1,
+            dot(rel, tangential(a2)) / l2,
+            dot(v1, radial(a1)),
+            dot(rel, radial(a2)),
+        ]
+    }
+
+    // p' = sum m (J'^T v + J^T a), using d/dt (l e_a) = l' e_a - l a' e_r and d/dt e_r = a' e_a.
+    fn canonical_derivative(&self, settings: &Settings, t: f64, x: &[f64; 8]) -> [f64; 8] {
+        let p = self.0;
+        let y = self.to_velocities(settings, x);
+        let [a1, a2, l1, l2, a1_v, a2_v, l1_v, l2_v] = y;
+        let (v1, v2) = self.velocities(&y);
+        let (acc1, acc2) = self.accelerations(settings, t, &y);
+
+        let both_v = add(scale(p.m1, v1), scale(p.m2, v2));
+        let both_a = add(scale(p.m1, acc1), scale(p.m2, acc2));
+        let (outer_v, outer_a) = (scale(p.m2, v2), scale(p.m2, acc2));
+        let (e_r1, e_a1, e_r2, e_a2) = (radial(a1), tangential(a1), radial(a2), tangential(a2));
+
+        [
+            a1_v,
+            a2_v,
+            l1_v,
+            l2_v,
+            dot(add(scale(l1_v, e_a1), scale(-l1 * a1_v, e_r1)), both_v) + l1 * dot(e_a1, both_a),
+            dot(add(scale(l2_v, e_a2), scale(-l2 * a2_v, e_r2)), outer_v) + l2 * dot(e_a2, outer_a),
+            a1_v * dot(e_a1, both_v) + dot(e_r1, both_a),
+            a2_v * dot(e_a2, outer_v) + dot(e_r2, outer_a),
+        ]
+    }
+}
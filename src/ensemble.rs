@@ -0,0 +1,317 @@
+use std::time::Instant;
+
+use crate::{Drive, Model, Pendulum, Settings};
+use crate::integrators::{Dynamics, Hamiltonian, Integrator, StepControl};
+use crate::simulation::{self, Arms, flip_time, pivot_motion, tilted_gravity};
+
+// Pendulums stepped together. A batch's state is one flat array laid out like a single pendulum's
+// (all the `a1`s, then the `a2`s, `a1_v`s and `a2_v`s), so the integrators advance the whole batch
+// at once and their per-element loops run over contiguous lanes.
+const LANES: usize = 8;
+type BatchState = [f64; 4 * LANES];
+
+// The ensemble as a struct of arrays, with one contiguous array per quantity instead of one
+// `Pendulum` each. It only covers the common case, see `Ensemble::supports`, and steps the
+// pendulums in its place whenever it can, keeping their states until they are stored back. The
+// arrays are padded to whole batches with pendulums hanging at rest.
+pub(crate) struct Ensemble {
+    a1: Vec<f64>,
+    a2: Vec<f64>,
+    a1_v: Vec<f64>,
+    a2_v: Vec<f64>,
+    r1: Vec<f64>,
+    r2: Vec<f64>,
+    m1: Vec<f64>,
+    m2: Vec<f64>,
+    g: Vec<f64>,
+    flip_time: Vec<Option<f64>>,
+    // Simulated time, shared by every pendulum.
+    t: f64,
+    // Steps each pendulum has taken since they were last stored.
+    steps: u64,
+}
+
+// A contiguous run of the ensemble's batches, which one thread steps.
+struct Share<'a> {
+    a1: &'a mut [f64],
+    a2: &'a mut [f64],
+    a1_v: &'a mut [f64],
+    a2_v: &'a mut [f64],
+    r1: &'a [f64],
+    r2: &'a [f64],
+    m1: &'a [f64],
+    m2: &'a [f64],
+    g: &'a [f64],
+    flip_time: &'a mut [Option<f64>],
+}
+
+// The constants of one batch.
+struct Batch {
+    r1: [f64; LANES],
+    r2: [f64; LANES],
+    m1: [f64; LANES],
+    m2: [f64; LANES],
+    g: [f64; LANES],
+}
+
+fn lanes(values: &[f64], start: usize) -> [f64; LANES] {
+    values[start..start + LANES].try_into().unwrap()
+}
+
+impl Ensemble {
+    // Whether the ensemble can step with these settings: any model but the elastic one, without
+    // friction or drag, and a fixed step integrator (the adaptive one would share its step size
+    // across a batch).
+    pub(crate) fn supports(settings: &Settings) -> bool {
+        settings.model != Model::Elastic
+            && settings.integrator != Integrator::Rk45
+            && settings.damping1 == 0.0
+            && settings.damping2 == 0.0
+            && settings.drag == 0.0
+    }
+
+    pub(crate) fn from_pendulums(pends: &[Pendulum]) -> Ensemble {
+        let padded = pends.len().div_ceil(LANES) * LANES;
+        let column = |value: fn(&Pendulum) -> f64, pad: f64| -> Vec<f64> {
+            let mut column: Vec<f64> = pends.iter().map(value).collect();
+            column.resize(padded, pad);
+            column
+        };
+        let mut flip_time: Vec<Option<f64>> = pends.iter().map(|p| p.flip_time).collect();
+        flip_time.resize(padded, None);
+        Ensemble {
+            a1: column(|p| p.a1, 0.0),
+            a2: column(|p| p.a2, 0.0),
+            a1_v: column(|p| p.a1_v, 0.0),
+            a2_v: column(|p| p.a2_v, 0.0),
+            r1: column(|p| p.r1, 1.0),
+            r2: column(|p| p.r2, 1.0),
+            m1: column(|p| p.m1, 1.0),
+            m2: column(|p| p.m2, 1.0),
+            g: column(|p| p.g, 0.0),
+            flip_time,
+            t: pends.first().map_or(0.0, |p| p.t),
+            steps: 0,
+        }
+    }
+
+    // Take in the states of `pends`, the pendulums this was made from, after something else has
+    // changed them.
+    pub(crate) fn load(&mut self, pends: &[Pendulum]) {
+        for (i, pend) in pends.iter().enumerate() {
+            [self.a1[i], self.a2[i], self.a1_v[i], self.a2_v[i]] = [pend.a1, pend.a2, pend.a1_v, pend.a2_v];
+            self.flip_time[i] = pend.flip_time;
+        }
+        self.t = pends.first().map_or(0.0, |p| p.t);
+    }
+
+    // Write the states back into `pends`, as if each had been stepped on its own.
+    pub(crate) fn store(&mut self, pends: &mut [Pendulum]) {
+        for (i, pend) in pends.iter_mut().enumerate() {
+            [pend.a1, pend.a2, pend.a1_v, pend.a2_v] = [self.a1[i], self.a2[i], self.a1_v[i], self.a2_v[i]];
+            pend.flip_time = self.flip_time[i];
+            pend.control.accepted += self.steps;
+            pend.t = self.t;
+        }
+        self.steps = 0;
+    }
+
+    // Split into runs of `size` pendulums, a multiple of `LANES`.
+    fn shares(&mut self, size: usize) -> Vec<Share<'_>> {
+        let states = self.a1.chunks_mut(size).zip(self.a2.chunks_mut(size)).zip(self.a1_v.chunks_mut(size)).zip(self.a2_v.chunks_mut(size));
+        let constants = self.r1.chunks(size).zip(self.r2.chunks(size)).zip(self.m1.chunks(size)).zip(self.m2.chunks(size)).zip(self.g.chunks(size));
+        states
+            .zip(constants)
+            .zip(self.flip_time.chunks_mut(size))
+            .map(|(((((a1, a2), a1_v), a2_v), ((((r1, r2), m1), m2), g)), flip_time)| Share { a1, a2, a1_v, a2_v, r1, r2, m1, m2, g, flip_time })
+            .collect()
+    }
+
+    // Advance every pendulum by one tick as `Pendulum::step` does, with the same threads as
+    // `simulation::tick`.
+    pub(crate) fn tick(&mut self, settings: &Settings) {
+        let batches = self.a1.len() / LANES;
+        let size = batches.div_ceil(settings.threads.max(1)).max(1) * LANES;
+        let t = self.t;
+        let shares = self.shares(size);
+        if shares.len() <= 1 {
+            for mut share in shares {
+                share.step(settings, t);
+            }
+        } else {
+            std::thread::scope(|scope| {
+                for mut share in shares {
+                    scope.spawn(move || share.step(settings, t));
+                }
+            });
+        }
+
+        // Sum the substeps like `Pendulum::step` does, so the drive's phase stays the same.
+        let span = settings.speed / settings.substeps.max(1) as f64;
+        for _ in 0..settings.substeps.max(1) {
+            self.t += span;
+        }
+        self.steps += settings.substeps.max(1) as u64;
+    }
+}
+
+impl Share<'_> {
+    fn step(&mut self, settings: &Settings, t: f64) {
+        let span = settings.speed / settings.substeps.max(1) as f64;
+        for start in (0..self.a1.len()).step_by(LANES) {
+            let batch = Batch {
+                r1: lanes(self.r1, start),
+                r2: lanes(self.r2, start),
+                m1: lanes(self.m1, start),
+                m2: lanes(self.m2, start),
+                g: lanes(self.g, start),
+            };
+            let mut y = [0.0; 4 * LANES];
+            for (part, values) in [&*self.a1, &*self.a2, &*self.a1_v, &*self.a2_v].into_iter().enumerate() {
+                y[part * LANES..(part + 1) * LANES].copy_from_slice(&values[start..start + LANES]);
+            }
+
+            let mut t = t;
+            let mut control = StepControl::default();
+            for _ in 0..settings.substeps.max(1) {
+                let before = y;
+                y = settings.integrator.advance(&batch, settings, t, &y, span, &mut control);
+                for (l, flip) in self.flip_time[start..start + LANES].iter_mut().enumerate() {
+                    if flip.is_none() {
+                        *flip = flip_time(before[LANES + l], y[LANES + l], t, span);
+                    }
+                }
+                t += span;
+            }
+
+            for (part, values) in [&mut *self.a1, &mut *self.a2, &mut *self.a1_v, &mut *self.a2_v].into_iter().enumerate() {
+                values[start..start + LANES].copy_from_slice(&y[part * LANES..(part + 1) * LANES]);
+            }
+        }
+    }
+}
+
+// The same equations as `Pendulum`, lane by lane.
+impl Batch {
+    fn arms(&self, l: usize) -> Arms {
+        Arms { r1: self.r1[l], r2: self.r2[l], m1: self.m1[l], m2: self.m2[l] }
+    }
+
+    // `[a1, a2, a1_v, a2_v]`, or the same with momenta, of lane `l`.
+    fn lane(y: &BatchState, l: usize) -> [f64; 4] {
+        [y[l], y[LANES + l], y[2 * LANES + l], y[3 * LANES + l]]
+    }
+
+    fn set_lane(y: &mut BatchState, l: usize, values: [f64; 4]) {
+        for (part, value) in values.into_iter().enumerate() {
+            y[part * LANES + l] = value;
+        }
+    }
+
+    // Angular accelerations of every lane, see `Pendulum::accelerations`.
+    fn accelerations(&self, settings: &Settings, t: f64, y: &BatchState) -> ([f64; LANES], [f64; LANES]) {
+        let pivot_a = match settings.drive {
+            Drive::Fixed => None,
+            _ => Some(pivot_motion(settings, t).2),
+        };
+        let mut a1_a = [0.0; LANES];
+        let mut a2_a = [0.0; LANES];
+        for l in 0..LANES {
+            let (g, tilt) = match pivot_a {
+                None => (self.g[l], 0.0),
+                Some(pivot_a) => tilted_gravity(self.g[l], pivot_a),
+            };
+            let [a1, a2, a1_v, a2_v] = Batch::lane(y, l);
+            (a1_a[l], a2_a[l]) = self.arms(l).accelerations(settings.model, g, &[a1 - tilt, a2 - tilt, a1_v, a2_v]);
+        }
+        (a1_a, a2_a)
+    }
+}
+
+impl Dynamics for Batch {
+    type State = BatchState;
+
+    fn derivative(&self, settings: &Settings, t: f64, y: &BatchState) -> BatchState {
+        let (a1_a, a2_a) = self.accelerations(settings, t, y);
+        let mut d = [0.0; 4 * LANES];
+        d[..2 * LANES].copy_from_slice(&y[2 * LANES..]);
+        d[2 * LANES..3 * LANES].copy_from_slice(&a1_a);
+        d[3 * LANES..].copy_from_slice(&a2_a);
+        d
+    }
+}
+
+impl Hamiltonian for Batch {
+    fn to_momenta(&self, settings: &Settings, y: &BatchState) -> BatchState {
+        let mut x = *y;
+        for l in 0..LANES {
+            Batch::set_lane(&mut x, l, self.arms(l).to_momenta(settings.model, &Batch::lane(y, l)));
+        }
+        x
+    }
+
+    fn to_velocities(&self, settings: &Settings, x: &BatchState) -> BatchState {
+        let mut y = *x;
+        for l in 0..LANES {
+            Batch::set_lane(&mut y, l, self.arms(l).to_velocities(settings.model, &Batch::lane(x, l)));
+        }
+        y
+    }
+
+    fn canonical_derivative(&self, settings: &Settings, t: f64, x: &BatchState) -> BatchState {
+        let y = self.to_velocities(settings, x);
+        let (a1_a, a2_a) = self.accelerations(settings, t, &y);
+        let mut d = [0.0; 4 * LANES];
+        d[..2 * LANES].copy_from_slice(&y[2 * LANES..]);
+        for l in 0..LANES {
+            let (p1_v, p2_v) = self.arms(l).momentum_rates(settings.model, &Batch::lane(&y, l), (a1_a[l], a2_a[l]));
+            d[2 * LANES + l] = p1_v;
+            d[3 * LANES + l] = p2_v;
+        }
+        d
+    }
+}
+
+// Time `ticks` ticks of the pendulums stepped one by one against the same ensemble as a struct of
+// arrays, and check that they end up in the same place.
+pub(crate) fn bench(pends: &[Pendulum], settings: &Settings, ticks: usize) {
+    if pends.is_empty() || !Ensemble::supports(settings) {
+        println!("The benchmark needs double pendulums with any model but elastic, no friction or drag and a fixed step integrator.");
+        return;
+    }
+
+    let mut aos = pends.to_vec();
+    let start = Instant::now();
+    for _ in 0..ticks {
+        simulation::tick(&mut aos, &mut [], settings);
+    }
+    let aos_time = start.elapsed().as_secs_f64();
+
+    let mut soa = pends.to_vec();
+    let mut ensemble = Ensemble::from_pendulums(pends);
+    let start = Instant::now();
+    for _ in 0..ticks {
+        ensemble.tick(settings);
+    }
+    let soa_time = start.elapsed().as_secs_f64();
+    ensemble.store(&mut soa);
+
+    let mut difference: f64 = 0.0;
+    for (a, b) in aos.iter().zip(&soa) {
+        for (a, b) in [a.a1, a.a2, a.a1_v, a.a2_v].iter().zip([b.a1, b.a2, b.a1_v, b.a2_v]) {
+            difference = difference.max((a - b).abs());
+        }
+    }
+
+    let steps = (pends.len() * ticks * settings.substeps.max(1) as usize) as f64;
+    println!(
+        "{} pendulums, {} ticks of {} substeps on {} threads",
+        pends.len(),
+        ticks,
+        settings.substeps.max(1),
+        settings.threads
+    );
+    println!("Array of structs: {:.3}s, {:.3e} pendulum steps/s", aos_time, steps / aos_time);
+    println!("Struct of arrays: {:.3}s, {:.3e} pendulum steps/s ({:.2}x)", soa_time, steps / soa_time, aos_time / soa_time);
+    println!("Largest difference in state: {:.3e}", difference);
+}
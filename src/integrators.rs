@@ -0,0 +1,325 @@
+use crate::Settings;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Integrator {
+    // Positions and velocities are both advanced from the start of the step (the original update).
+    Euler,
+    // Velocities are advanced first and the positions use the new velocities.
+    SemiImplicitEuler,
+    // Classic fourth order Runge-Kutta.
+    Rk4,
+    // Velocity Verlet on the canonical momenta (generalised leapfrog), symplectic and second order.
+    Verlet,
+    // Yoshida's fourth order composition of three Verlet steps, also symplectic.
+    Yoshida4,
+    // Adaptive Dormand-Prince 5(4), sub-stepping to stay within `rtol`/`atol`.
+    Rk45,
+}
+
+// Per-pendulum integrator bookkeeping: the step size the adaptive integrator carries between
+// frames, and the step counts reported at the end of a run.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct StepControl {
+    pub(crate) h: f64,
+    pub(crate) accepted: u64,
+    pub(crate) rejected: u64,
+}
+
+// Anything the integrators can advance. The state holds the positions in its first half
+// and the matching velocities in its second half.
+pub(crate) trait Dynamics {
+    type State: Clone + AsRef<[f64]> + AsMut<[f64]>;
+
+    // Time derivative of `y` at time `t`, i.e. the velocities followed by the accelerations.
+    fn derivative(&self, settings: &Settings, t: f64, y: &Self::State) -> Self::State;
+}
+
+// Systems with a canonical (Hamiltonian) form, used by the symplectic integrators.
+pub(crate) trait Hamiltonian: Dynamics {
+    // `[q, q_v]` -> `[q, p]`
+    fn to_momenta(&self, settings: &Settings, y: &Self::State) -> Self::State;
+    // `[q, p]` -> `[q, q_v]`
+    fn to_velocities(&self, settings: &Settings, x: &Self::State) -> Self::State;
+    // Time derivative of the canonical state `[q, p]` at time `t`.
+    fn canonical_derivative(&self, settings: &Settings, t: f64, x: &Self::State) -> Self::State;
+}
+
+// Fixed-point iteration limits for the implicit parts of the leapfrog step.
+const LEAPFROG_MAX_ITERS: usize = 20;
+const LEAPFROG_TOL: f64 = 1e-13;
+
+// Step size control for the adaptive integrator.
+const SAFETY: f64 = 0.9;
+const MIN_SCALE: f64 = 0.2;
+const MAX_SCALE: f64 = 5.0;
+
+// Dormand-Prince 5(4) tableau.
+const DP_C: [f64; 6] = [1.0 / 5.0, 3.0 / 10.0, 4.0 / 5.0, 8.0 / 9.0, 1.0, 1.0];
+const DP_A: [[f64; 6]; 6] = [
+    [1.0 / 5.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [3.0 / 40.0, 9.0 / 40.0, 0.0, 0.0, 0.0, 0.0],
+    [44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0, 0.0, 0.0, 0.0],
+    [19372.0 / 6561.0, -25360.0 / 2187.0, 64448.0 / 6561.0, -212.0 / 729.0, 0.0, 0.0],
+    [9017.0 / 3168.0, -355.0 / 33.0, 46732.0 / 5247.0, 49.0 / 176.0, -5103.0 / 18656.0, 0.0],
+    [35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0],
+];
+// Difference between the fifth and fourth order weights, used for the error estimate.
+const DP_E: [f64; 7] = [
+    71.0 / 57600.0,
+    0.0,
+    -71.0 / 16695.0,
+    71.0 / 1920.0,
+    -17253.0 / 339200.0,
+    22.0 / 525.0,
+    -1.0 / 40.0,
+];
+
+// y + h * d
+fn axpy<S: Clone + AsRef<[f64]> + AsMut<[f64]>>(y: &S, h: f64, d: &S) -> S {
+    let mut out = y.clone();
+    for (o, d) in out.as_mut().iter_mut().zip(d.as_ref()) {
+        *o += h * d;
+    }
+    out
+}
+
+// y + h * sum(w_i * k_i)
+fn combine<S: Clone + AsRef<[f64]> + AsMut<[f64]>>(y: &S, h: f64, weights: &[f64], ks: &[S]) -> S {
+    let mut out = y.clone();
+    for (w, k) in weights.iter().zip(ks) {
+        if *w != 0.0 {
+            for (o, k) in out.as_mut().iter_mut().zip(k.as_ref()) {
+                *o += h * w * k;
+            }
+        }
+    }
+    out
+}
+
+// One Dormand-Prince step, returning the fifth order solution and its scaled error norm.
+fn dopri5<D: Dynamics>(sys: &D, settings: &Settings, t: f64, y: &D::State, h: f64) -> (D::State, f64) {
+    let mut ks = vec![sys.derivative(settings, t, y)];
+    for (row, c) in DP_A.iter().zip(DP_C) {
+        let stage = combine(y, h, row, &ks);
+        ks.push(sys.derivative(settings, t + c * h, &stage));
+    }
+    // The last stage is evaluated at the new solution.
+    let y_new = combine(y, h, &DP_A[5], &ks);
+
+    let mut sum = 0.0;
+    let n = y.as_ref().len();
+    for i in 0..n {
+        let err: f64 = DP_E.iter().zip(&ks).map(|(e, k)| e * k.as_ref()[i]).sum::<f64>() * h;
+        let scale = settings.atol + settings.rtol * y.as_ref()[i].abs().max(y_new.as_ref()[i].abs());
+        sum += (err / scale).powi(2);
+    }
+    (y_new, (sum / n as f64).sqrt())
+}
+
+// Generalised leapfrog (Stormer-Verlet) step on `[q, p]`. The double pendulum's Hamiltonian is
+// not separable, so the half-step momenta and the new positions are implicit and found by
+// fixed-point iteration.
+fn leapfrog<D: Hamiltonian>(sys: &D, settings: &Settings, t: f64, x: &D::State, h: f64) -> D::State {
+    let half = x.as_ref().len() / 2;
+
+    // p_half = p + h/2 * p'(q, p_half)
+    let mut mid = x.clone();
+    for _ in 0..LEAPFROG_MAX_ITERS {
+        let d = sys.canonical_derivative(settings, t, &mid);
+        let mut change: f64 = 0.0;
+        for i in half..mid.as_ref().len() {
+            let p = x.as_ref()[i] + h / 2.0 * d.as_ref()[i];
+            change = change.max((p - mid.as_ref()[i]).abs() / p.abs().max(1.0));
+            mid.as_mut()[i] = p;
+        }
+        if change < LEAPFROG_TOL {
+            break;
+        }
+    }
+
+    // q_new = q + h/2 * (q'(q, p_half) + q'(q_new, p_half))
+    let start = sys.canonical_derivative(settings, t, &mid);
+    let mut end = axpy(&mid, h, &start);
+    end.as_mut()[half..].copy_from_slice(&mid.as_ref()[half..]);
+    for _ in 0..LEAPFROG_MAX_ITERS {
+        let d = sys.canonical_derivative(settings, t + h, &end);
+        let mut change: f64 = 0.0;
+        for i in 0..half {
+            let q = x.as_ref()[i] + h / 2.0 * (start.as_ref()[i] + d.as_ref()[i]);
+            change = change.max((q - end.as_ref()[i]).abs() / q.abs().max(1.0));
+            end.as_mut()[i] = q;
+        }
+        if change < LEAPFROG_TOL {
+            break;
+        }
+    }
+
+    // p_new = p_half + h/2 * p'(q_new, p_half)
+    let d = sys.canonical_derivative(settings, t + h, &end);
+    for i in half..end.as_ref().len() {
+        end.as_mut()[i] += h / 2.0 * d.as_ref()[i];
+    }
+    end
+}
+
+impl Integrator {
+    // Advance `y` from time `t` by `span`. The fixed step integrators take a single step, the adaptive
+    // one sub-steps starting from `control.h` and leaves the last accepted step size in it.
+    pub(crate) fn advance<D: Hamiltonian>(
+        self,
+        sys: &D,
+        settings: &Settings,
+        t: f64,
+        y: &D::State,
+        span: f64,
+        control: &mut StepControl,
+    ) -> D::State {
+        if self != Integrator::Rk45 {
+            control.accepted += 1;
+            return self.step(sys, settings, t, y, span);
+        }
+
+        if !(control.h > 0.0 && control.h.is_finite()) {
+            control.h = span;
+        }
+        let mut y = y.clone();
+        let mut elapsed = 0.0;
+        while elapsed < span {
+            let last = control.h >= span - elapsed;
+            let step = if last { span - elapsed } else { control.h };
+            let (y_new, err) = dopri5(sys, settings, t + elapsed, &y, step);
+
+            // A NaN or infinite state (e.g. from a massless bob) won't get better with a smaller
+            // step and would turn the step size into NaN, so take it and stop here.
+            if !err.is_finite() || y_new.as_ref().iter().any(|v| !v.is_finite()) {
+                control.accepted += 1;
+                return y_new;
+            }
+
+            // Never go below a tiny fraction of the span, so a bad state cannot stall the run.
+            if err <= 1.0 || step <= span * 1e-9 {
+                y = y_new;
+                elapsed = if last { span } else { elapsed + step };
+                control.accepted += 1;
+                let scale = if err == 0.0 { MAX_SCALE } else { SAFETY * err.powf(-0.2) };
+                // The final step was cut to fit the span, so don't let it shrink the next one.
+                if !last || scale < 1.0 {
+                    control.h = step * scale.clamp(MIN_SCALE, MAX_SCALE);
+                }
+            } else {
+                control.rejected += 1;
+                control.h = step * (SAFETY * err.powf(-0.2)).clamp(MIN_SCALE, 1.0);
+            }
+        }
+        y
+    }
+
+    // Advance `y` from time `t` by a single step of size `h`.
+    fn step<D: Hamiltonian>(self, sys: &D, settings: &Settings, t: f64, y: &D::State, h: f64) -> D::State {
+        match self {
+            Integrator::Euler => axpy(y, h, &sys.derivative(settings, t, y)),
+            Integrator::SemiImplicitEuler => {
+                let d = sys.derivative(settings, t, y);
+                let mut out = y.clone();
+                let half = out.as_ref().len() / 2;
+                let (q, v) = out.as_mut().split_at_mut(half);
+                for (v, a) in v.iter_mut().zip(&d.as_ref()[half..]) {
+                    *v += h * a;
+                }
+                for (q, v) in q.iter_mut().zip(v.iter()) {
+                    *q += h * v;
+                }
+                out
+            }
+            Integrator::Rk4 => {
+                let k1 = sys.derivative(settings, t, y);
+                let k2 = sys.derivative(settings, t + h / 2.0, &axpy(y, h / 2.0, &k1));
+                let k3 = sys.derivative(settings, t + h / 2.0, &axpy(y, h / 2.0, &k2));
+                let k4 = sys.derivative(settings, t + h, &axpy(y, h, &k3));
+
+                let mut out = y.clone();
+                let parts = k1.as_ref().iter().zip(k2.as_ref()).zip(k3.as_ref()).zip(k4.as_ref());
+                for (o, (((k1, k2), k3), k4)) in out.as_mut().iter_mut().zip(parts) {
+                    *o += h / 6.0 * (k1 + 2.0 * k2 + 2.0 * k3 + k4);
+                }
+                out
+            }
+            Integrator::Verlet => {
+                let x = leapfrog(sys, settings, t, &sys.to_momenta(settings, y), h);
+                sys.to_velocities(settings, &x)
+            }
+            Integrator::Yoshida4 => {
+                let cbrt2 = 2.0f64.cbrt();
+                let w1 = 1.0 / (2.0 - cbrt2);
+                let w0 = -cbrt2 / (2.0 - cbrt2);
+
+                let mut x = sys.to_momenta(settings, y);
+                let mut t = t;
+                for w in [w1, w0, w1] {
+                    x = leapfrog(sys, settings, t, &x, w * h);
+                    t += w * h;
+                }
+                sys.to_velocities(settings, &x)
+            }
+            Integrator::Rk45 => dopri5(sys, settings, t, y, h).0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // x'' = -k x, which is its own canonical form with unit mass.
+    struct Oscillator(f64);
+
+    impl Dynamics for Oscillator {
+        type State = [f64; 2];
+
+        fn derivative(&self, _settings: &Settings, _t: f64, y: &[f64; 2]) -> [f64; 2] {
+            [y[1], -self.0 * y[0]]
+        }
+    }
+
+    impl Hamiltonian for Oscillator {
+        fn to_momenta(&self, _settings: &Settings, y: &[f64; 2]) -> [f64; 2] {
+            *y
+        }
+
+        fn to_velocities(&self, _settings: &Settings, x: &[f64; 2]) -> [f64; 2] {
+            *x
+        }
+
+        fn canonical_derivative(&self, settings: &Settings, t: f64, x: &[f64; 2]) -> [f64; 2] {
+            self.derivative(settings, t, x)
+        }
+    }
+
+    #[test]
+    fn rk45_reaches_the_span_within_tolerance() {
+        let settings = Settings::new();
+        let mut control = StepControl::default();
+        let y = Integrator::Rk45.advance(&Oscillator(1.0), &settings, 0.0, &[1.0, 0.0], 10.0, &mut control);
+        assert!((y[0] - 10.0f64.cos()).abs() < 1e-5);
+        assert!((y[1] + 10.0f64.sin()).abs() < 1e-5);
+        assert!(control.accepted > 1);
+    }
+
+    #[test]
+    fn rk45_rejects_steps_on_a_stiff_system() {
+        let settings = Settings::new();
+        let mut control = StepControl::default();
+        let y = Integrator::Rk45.advance(&Oscillator(1e4), &settings, 0.0, &[1.0, 0.0], 1.0, &mut control);
+        assert!(control.rejected > 0);
+        assert!(y[0].abs() < 1.0 + 1e-3);
+    }
+
+    #[test]
+    fn rk45_stops_on_a_nan_state() {
+        let settings = Settings::new();
+        let mut control = StepControl::default();
+        let y = Integrator::Rk45.advance(&Oscillator(f64::NAN), &settings, 0.0, &[1.0, 0.0], 1.0, &mut control);
+        assert!(y[1].is_nan());
+        assert!(control.h.is_finite());
+    }
+}
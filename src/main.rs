@@ -9,7 +9,7 @@ use std::time::Instant;
 
 use colors_transform::{Color, Hsl, Rgb};
 use glutin_window::GlutinWindow as Window;
-use graphics::{CircleArc, DrawState, Ellipse, Graphics, Line};
+use graphics::{CircleArc, DrawState, Ellipse, Line};
 use graphics::types::Matrix2d;
 use opengl_graphics::{GlGraphics, OpenGL};
 use piston::{UpdateArgs, UpdateEvent};
@@ -17,7 +17,23 @@ use piston::event_loop::{Events, EventSettings};
 use piston::input::{RenderArgs, RenderEvent};
 use piston::window::WindowSettings;
 
+use analysis::{Analysis, Exports, Section};
+use compile_pngs::VideoSettings;
+use chain::Chain;
+use integrators::{Integrator, StepControl};
+use simulation::Stepper;
+
+mod analysis;
+mod chain;
 mod compile_pngs;
+mod elastic;
+mod ensemble;
+mod integrators;
+mod simulation;
+
+// Most real time, in seconds, the window's physics falls behind before it drops the backlog
+// rather than spiral trying to catch up.
+const MAX_LAG: f64 = 0.25;
 
 #[derive(Clone, Copy, PartialEq)]
 enum Quality {
@@ -34,6 +50,59 @@ enum VaryingType {
     Length2 = 2,
     Mass1 = 3,
     Mass2 = 4,
+    Angle1 = 5,
+    Angle2 = 6,
+    Velocity1 = 7,
+    Velocity2 = 8,
+    Gravity = 9,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Model {
+    // Full nonlinear equations of motion.
+    Exact,
+    // The original equations, which use `a1` in place of `sin(a1)` in the first term.
+    // Kept so old renders can be reproduced.
+    Legacy,
+    // Each arm is a uniform rigid rod with its mass spread along it, like a desk-toy pendulum.
+    Compound,
+    // The arms are springs with rest lengths `r1` and `r2`.
+    Elastic,
+}
+
+// What each pixel of the fractal map shows.
+#[derive(Clone, Copy, PartialEq)]
+enum FractalColor {
+    // Hue from the angle of the outer arm.
+    Angle,
+    // Kinetic energy as a share of the potential depth, from blue (at rest) to red.
+    Energy,
+    // White once the outer arm has swung over the top.
+    Flipped,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum View {
+    // The pendulums' arms as they swing.
+    Physical,
+    // Each pendulum as a point in the plane of `Settings::phase_axes`.
+    Phase,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PhaseAxis {
+    Angle1,
+    Angle2,
+    Velocity1,
+    Velocity2,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Drive {
+    Fixed,
+    // The pivot oscillates along x or y as `drive_amp * cos(drive_freq * t)`.
+    Horizontal,
+    Vertical,
 }
 
 #[derive(Clone, Copy)]
@@ -46,7 +115,24 @@ struct Pendulum {
     a2: f64,
     a1_v: f64,
     a2_v: f64,
+    // Current arm lengths and their rates of change, only free in the elastic model.
+    l1: f64,
+    l2: f64,
+    l1_v: f64,
+    l2_v: f64,
+    // Gravity for this pendulum, in place of `Settings::g` so it can be swept.
+    g: f64,
+    // Column and row of this pendulum in the parameter grid, and the swept values there.
+    cell: (usize, usize),
+    sweep: [f64; 2],
     color: Rgb,
+    // Simulated time at which the second arm first swung past straight up.
+    flip_time: Option<f64>,
+    control: StepControl,
+    // Total energy before the first step.
+    e0: Option<f64>,
+    // Simulated time.
+    t: f64,
 }
 
 #[derive(Clone, Copy)]
@@ -61,8 +147,42 @@ struct Settings {
     pend_transp: f64,
     pend_width: f64,
     speed: f64,
+    substeps: u32,
+    tick_rate: f64,
+    // Threads stepping the ensemble, 0 until it is set to one per core.
+    threads: usize,
     quality: Quality,
     varying: VaryingType,
+    // Parameter swept down the rows of the grid, if any.
+    varying2: Option<VaryingType>,
+    // Columns and rows of the parameter grid, `varying` is swept along the columns.
+    grid: (usize, usize),
+    // Render one pixel per pendulum of the grid instead of drawing the pendulums.
+    fractal: Option<FractalColor>,
+    view: View,
+    // Horizontal and vertical axes of the phase view.
+    phase_axes: (PhaseAxis, PhaseAxis),
+    model: Model,
+    integrator: Integrator,
+    rtol: f64,
+    atol: f64,
+    // Viscous friction at the pivot and at the middle joint.
+    damping1: f64,
+    damping2: f64,
+    // Quadratic air drag coefficient of each bob.
+    drag: f64,
+    // Spring constant and damping of the arms in the elastic model.
+    stiffness: f64,
+    spring_damping: f64,
+    drive: Drive,
+    drive_amp: f64,
+    drive_freq: f64,
+    // Initial angles from straight down and angular velocities, in radians. The sweeps are added
+    // on top of these.
+    a1: f64,
+    a2: f64,
+    a1_v: f64,
+    a2_v: f64,
     // amt_pend: i32,
 }
 
@@ -79,71 +199,82 @@ impl Settings {
             pend_transp: 0.05,
             pend_width: 1.5,
             speed: 1.0,
+            substeps: 1,
+            tick_rate: 60.0,
+            threads: 0,
             quality: Quality::Low,
             varying: VaryingType::Angle,
+            varying2: None,
+            grid: (50_000, 1),
+            fractal: None,
+            view: View::Physical,
+            phase_axes: (PhaseAxis::Angle1, PhaseAxis::Angle2),
+            model: Model::Exact,
+            integrator: Integrator::Euler,
+            rtol: 1e-6,
+            atol: 1e-9,
+            damping1: 0.0,
+            damping2: 0.0,
+            drag: 0.0,
+            stiffness: 0.5,
+            spring_damping: 0.0,
+            drive: Drive::Fixed,
+            drive_amp: 10.0,
+            drive_freq: 1.0,
+            a1: -PI / 2.0,
+            a2: -PI / 2.0,
+            a1_v: 0.0,
+            a2_v: 0.0,
             // amt_pend: 50_000,
         }
     }
+
+    // These settings with gravity `g`, for pendulums that carry their own.
+    fn with_gravity(&self, g: f64) -> Settings {
+        Settings { g, ..*self }
+    }
 }
 
 impl Pendulum {
     // Constructor
-    fn new(d: f64, d2: f64, r1i: f64, r2i: f64, m1i: f64, m2i: f64, color: Rgb) -> Pendulum {
+    fn new(a: [f64; 2], a_v: [f64; 2], r: [f64; 2], m: [f64; 2], g: f64, color: Rgb) -> Pendulum {
         Pendulum {
-            r1: r1i,
-            r2: r2i,
-            m1: m1i,
-            m2: m2i,
-            a1: PI / d,
-            a2: PI / d2,
-            a1_v: 0.0,
-            a2_v: 0.0,
+            r1: r[0],
+            r2: r[1],
+            m1: m[0],
+            m2: m[1],
+            a1: a[0],
+            a2: a[1],
+            a1_v: a_v[0],
+            a2_v: a_v[1],
+            l1: r[0],
+            l2: r[1],
+            l1_v: 0.0,
+            l2_v: 0.0,
+            g,
+            cell: (0, 0),
+            sweep: [0.0; 2],
             color,
+            flip_time: None,
+            control: StepControl::default(),
+            e0: None,
+            t: 0.0,
         }
     }
 
 
-    fn update_draw(
-        mut self,
-        settings: Settings,
-        transform: Matrix2d,
-        gl: &mut GlGraphics,
-    ) -> Pendulum {
-        let a1 = self.a1;
-        let a2 = self.a2;
-        let a1_v = self.a1_v;
-        let a2_v = self.a2_v;
-        let r1 = self.r1;
-        let r2 = self.r2;
+    fn draw(&self, settings: &Settings, transform: Matrix2d, gl: &mut GlGraphics) {
         let m1 = self.m1;
         let m2 = self.m2;
-        let g = settings.g;
-
-        let mut num1 = -g * (2.0 * m1 + m2) * (a1);
-        let mut num2 = -m2 * g * (a1 - 2.0 * a2).sin();
-        let mut num3 = -2.0 * (a1 - a2).sin() * m2;
-        let mut num4 = a2_v * a2_v * r2 + a1_v * a1_v * r1 * (a1 - a2).cos();
-        let mut den = r1 * (2.0 * m1 + m2 - m2 * (2.0 * a1 - 2.0 * a2).cos());
-        let a1_a = (num1 + num2 + num3 * num4) / den;
-
-        num1 = 2.0 * (a1 - a2).sin();
-        num2 = a1_v * a1_v * r1 * (m1 + m2);
-        num3 = g * (m1 + m2) * (a1).cos();
-        num4 = a2_v * a2_v * r2 * m2 * (a1 - a2).cos();
-        den = r2 * (2.0 * m1 + m2 - m2 * (2.0 * a1 - 2.0 * a2).cos());
-        let a2_a = (num1 * (num2 + num3 + num4)) / den;
-
-        let x1 = r1 * (a1).sin();
-        let y1 = r1 * (a1).cos();
-
-        let x2 = x1 + r2 * (a2).sin();
-        let y2 = y1 + r2 * (a2).cos();
+        let (px, py) = self.pivot(settings);
+        let [(x1, y1), (x2, y2)] = self.positions();
+        let (x1, y1, x2, y2) = (x1 + px, y1 + py, x2 + px, y2 + py);
 
         let line_t = Line::new(
             [
-                self.color.get_red() as f32 / 255.0,
-                self.color.get_blue() as f32 / 255.0,
-                self.color.get_green() as f32 / 255.0,
+                self.color.get_red() / 255.0,
+                self.color.get_blue() / 255.0,
+                self.color.get_green() / 255.0,
                 settings.pend_transp as f32,
             ],
             settings.pend_width,
@@ -152,7 +283,7 @@ impl Pendulum {
         let mag = settings.mag;
 
         line_t.draw(
-            [0.0, 0.0, x1 * mag, y1 * mag],
+            [px * mag, py * mag, x1 * mag, y1 * mag],
             &DrawState::default(),
             transform,
             gl,
@@ -218,56 +349,239 @@ impl Pendulum {
                 gl,
             );
         }
+    }
+}
+
+// A pendulum's point in the phase view, in its colour with the usual transparency.
+fn draw_phase_point(point: (f64, f64), color: Rgb, settings: &Settings, transform: Matrix2d, gl: &mut GlGraphics) {
+    let circle_t = Ellipse::new([
+        color.get_red() / 255.0,
+        color.get_blue() / 255.0,
+        color.get_green() / 255.0,
+        settings.pend_transp as f32,
+    ]);
+    let (x, y) = (point.0 * settings.mag, point.1 * settings.mag);
+    let size = settings.pend_width;
+    circle_t.draw([x - size / 2.0, y - size / 2.0, size, size], &DrawState::default(), transform, gl);
+}
 
-        self.a1_v += a1_a * settings.speed;
-        self.a2_v += a2_a * settings.speed;
-        self.a1 += a1_v * settings.speed;
-        self.a2 += a2_v * settings.speed;
+impl Chain {
+    fn draw(&self, settings: &Settings, transform: Matrix2d, gl: &mut GlGraphics) {
+        let color = [
+            self.color.get_red() / 255.0,
+            self.color.get_blue() / 255.0,
+            self.color.get_green() / 255.0,
+            settings.pend_transp as f32,
+        ];
+        let line_t = Line::new(color, settings.pend_width);
+        let circle_t = Ellipse::new(color);
+        let mag = settings.mag;
 
-        self
+        let (px, py) = self.pivot(settings);
+        let (mut x0, mut y0) = (px, py);
+        for ((x, y), m) in self.positions().into_iter().zip(&self.masses) {
+            let (x, y) = (x + px, y + py);
+            line_t.draw([x0 * mag, y0 * mag, x * mag, y * mag], &DrawState::default(), transform, gl);
+            if settings.quality != Quality::Low {
+                circle_t.draw([x * mag - (m / 2.0), y * mag - (m / 2.0), *m, *m], &DrawState::default(), transform, gl);
+            }
+            (x0, y0) = (x, y);
+        }
     }
 }
 
 pub struct App {
     gl: GlGraphics,
     pends: Vec<Pendulum>,
+    chains: Vec<Chain>,
     settings: Settings,
+    analysis: Analysis,
+    stepper: Stepper,
     fps_counter: u32,
     last_update: Instant,
+    // When the physics last caught up, and the real time since then not yet simulated, in seconds.
+    last_tick: Instant,
+    accumulator: f64,
+    // Ticks dropped since the last FPS report because the physics fell too far behind.
+    dropped: u64,
 }
 
 impl App {
     fn render(&mut self, args: &RenderArgs) {
         use graphics::*;
 
+        self.stepper.sync(&mut self.pends);
         let (mid_x, mid_y) = (args.window_size[0] / 2.0, args.window_size[1] / 2.0);
 
         self.gl.draw(args.viewport(), |c, gl| {
             // Clear the screen.
             clear([0.2, 0.2, 0.2, 1.0], gl);
 
-            for ui in 0..self.pends.len() {
-                //((time/0.5) as u8)  {
-                let new_transform = c.transform.trans(mid_x, mid_y);
+            let new_transform = c.transform.trans(mid_x, mid_y);
+            if self.settings.view == View::Phase {
+                for swinging in simulation::swinging(&self.pends, &self.chains) {
+                    draw_phase_point(swinging.phase_point(&self.settings), swinging.color(), &self.settings, new_transform, gl);
+                }
+                return;
+            }
 
-                self.pends[ui] = self.pends[ui].update_draw(self.settings, new_transform, gl);
+            for pend in self.pends.iter() {
+                //((time/0.5) as u8)  {
+                pend.draw(&self.settings, new_transform, gl);
+            }
+            for chain in self.chains.iter() {
+                chain.draw(&self.settings, new_transform, gl);
             }
         });
     }
 
-    // Update function to print the fps to the console.
-    fn update(&mut self, args: &UpdateArgs) {
-        if (self.fps_counter % 10) == 0 {
+    // Update function to step the physics at `tick_rate` ticks per second of real time and print the
+    // fps and energy drift to the console. The update events only say when to catch up, since
+    // piston's `dt` is a fixed 1/ups however long the update actually took.
+    fn update(&mut self, _args: &UpdateArgs) {
+        let now = Instant::now();
+        self.accumulator += now.duration_since(self.last_tick).as_secs_f64();
+        self.last_tick = now;
+        let tick_dt = 1.0 / self.settings.tick_rate;
+        if self.accumulator > MAX_LAG {
+            let behind = ((self.accumulator - MAX_LAG) / tick_dt) as u64;
+            self.dropped += behind;
+            self.accumulator -= behind as f64 * tick_dt;
+        }
+        while self.accumulator >= tick_dt {
+            self.stepper.tick(&mut self.pends, &mut self.chains, &self.settings);
+            if self.analysis.active() {
+                self.stepper.sync(&mut self.pends);
+            }
+            self.analysis.tick(&self.pends, &self.chains, &self.settings);
+            self.accumulator -= tick_dt;
+        }
+
+        if self.fps_counter.is_multiple_of(10) {
             let now = Instant::now();
             let elapsed = now.duration_since(self.last_update);
             let fps = 10.0 / elapsed.as_secs_f64();
-            println!("FPS: {}", fps);
+            self.stepper.sync(&mut self.pends);
+            match simulation::energy_drift_summary(&self.pends, &self.chains, &self.settings) {
+                Some(drift) => println!("FPS: {:.1} | energy drift {}", fps, drift),
+                None => println!("FPS: {:.1}", fps),
+            }
+            if self.dropped > 0 {
+                println!("The physics can't keep up with {} ticks per second, dropped {} ticks", self.settings.tick_rate, self.dropped);
+                self.dropped = 0;
+            }
             self.last_update = now;
         }
         self.fps_counter += 1;
     }
 }
 
+// Per-link values from a `--lengths`/`--masses` list, defaulting to the two arm settings. Links
+// past the end of the list repeat its last value.
+fn link_values(given: &[f64], first: f64, second: f64, links: usize) -> Vec<f64> {
+    let base = if given.is_empty() { vec![first, second] } else { given.to_vec() };
+    (0..links).map(|i| base[i.min(base.len() - 1)]).collect()
+}
+
+fn parse_phase_axis(arg: &str) -> PhaseAxis {
+    match arg {
+        "a1" => PhaseAxis::Angle1,
+        "a2" => PhaseAxis::Angle2,
+        "a1_v" => PhaseAxis::Velocity1,
+        "a2_v" => PhaseAxis::Velocity2,
+        _ => PhaseAxis::Angle1,
+    }
+}
+
+// An angle (or angular velocity) in degrees, or in radians with a `rad` suffix.
+fn parse_angle(arg: &str) -> f64 {
+    match arg.strip_suffix("rad") {
+        Some(rad) => rad.parse().unwrap(),
+        None => arg.trim_end_matches("deg").parse::<f64>().unwrap().to_radians(),
+    }
+}
+
+impl VaryingType {
+    // Apply step `i` of `n` of this sweep to `pend`, built from the unswept settings, and return
+    // the swept parameter's value.
+    fn apply(self, pend: &mut Pendulum, i: usize, n: usize, amt_sep: f64, settings: &Settings) -> f64 {
+        match self {
+            VaryingType::Angle => {
+                pend.a1 += angle_offset(i, n, amt_sep);
+                pend.a2 += angle_offset(i, n, amt_sep);
+            }
+            // The single arm sweeps spread evenly over `amt_sep` radians, so a grid of them can
+            // cover every starting position for the fractal map. Angular velocities likewise
+            // spread up to `amt_sep` radians per unit time.
+            VaryingType::Angle1 => pend.a1 += i as f64 * (amt_sep / n as f64),
+            VaryingType::Angle2 => pend.a2 += i as f64 * (amt_sep / n as f64),
+            VaryingType::Velocity1 => pend.a1_v += i as f64 * (amt_sep / n as f64),
+            VaryingType::Velocity2 => pend.a2_v += i as f64 * (amt_sep / n as f64),
+            VaryingType::Length1 => {
+                pend.r1 += i as f64 * (settings.max_m1 / n as f64);
+                pend.l1 = pend.r1;
+            }
+            VaryingType::Length2 => {
+                pend.r2 += i as f64 * (settings.max_m2 / n as f64);
+                pend.l2 = pend.r2;
+            }
+            VaryingType::Mass1 => pend.m1 = i as f64 * (settings.max_m1 / n as f64),
+            VaryingType::Mass2 => pend.m2 = i as f64 * (settings.max_m2 / n as f64),
+            // Like the mass sweeps, gravity goes from 0 up to one step short of `settings.g`.
+            VaryingType::Gravity => pend.g = i as f64 * (settings.g / n as f64),
+        }
+        match self {
+            VaryingType::Angle | VaryingType::Angle1 => pend.a1,
+            VaryingType::Angle2 => pend.a2,
+            VaryingType::Velocity1 => pend.a1_v,
+            VaryingType::Velocity2 => pend.a2_v,
+            VaryingType::Length1 => pend.r1,
+            VaryingType::Length2 => pend.r2,
+            VaryingType::Mass1 => pend.m1,
+            VaryingType::Mass2 => pend.m2,
+            VaryingType::Gravity => pend.g,
+        }
+    }
+
+    // Name on the command line, also used as a column header.
+    fn name(self) -> &'static str {
+        match self {
+            VaryingType::Angle => "angle",
+            VaryingType::Angle1 => "angle1",
+            VaryingType::Angle2 => "angle2",
+            VaryingType::Velocity1 => "velocity1",
+            VaryingType::Velocity2 => "velocity2",
+            VaryingType::Length1 => "length1",
+            VaryingType::Length2 => "length2",
+            VaryingType::Mass1 => "mass1",
+            VaryingType::Mass2 => "mass2",
+            VaryingType::Gravity => "gravity",
+        }
+    }
+}
+
+fn parse_varying(arg: &str) -> VaryingType {
+    match arg {
+        "angle" => VaryingType::Angle,
+        "angle1" => VaryingType::Angle1,
+        "angle2" => VaryingType::Angle2,
+        "velocity1" => VaryingType::Velocity1,
+        "velocity2" => VaryingType::Velocity2,
+        "length1" => VaryingType::Length1,
+        "length2" => VaryingType::Length2,
+        "mass1" => VaryingType::Mass1,
+        "mass2" => VaryingType::Mass2,
+        "gravity" => VaryingType::Gravity,
+        _ => VaryingType::Angle,
+    }
+}
+
+// Angle added to both arms of pendulum `i` when varying the angle. This is the original sweep of
+// `a = PI / (-2 + i * sep / n)` measured from its start at -90 degrees.
+fn angle_offset(i: usize, amt_pend: usize, amt_sep: f64) -> f64 {
+    PI / (-2.0 + (i as f64 * (amt_sep / amt_pend as f64))) + PI / 2.0
+}
+
 pub fn main() {
     let mut amt_pend: usize = 50_000;
     let mut amt_sep: f64 = 0.1;
@@ -278,8 +592,26 @@ pub fn main() {
 
     // Parse command line arguments
     let mut compile = false;
+    let mut video = VideoSettings::default();
     let mut compile_frames = 50;
-    for i in 0..args.len() {
+    let mut links: usize = 2;
+    let mut link_lengths: Vec<f64> = Vec::new();
+    let mut link_masses: Vec<f64> = Vec::new();
+    let mut grid: Option<(usize, usize)> = None;
+    let mut exports = Exports::default();
+    let mut bench: Option<usize> = None;
+    // Anything after `--` is passed through to ffmpeg.
+    let end = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+    video.extra_args = args[(end + 1).min(args.len())..].to_vec();
+    // Most flags take the next argument as their value, which is skipped so that values like the
+    // negative angles are never read as flags.
+    let mut skip = false;
+    for i in 0..end {
+        if skip {
+            skip = false;
+            continue;
+        }
+        skip = true;
         match args[i].as_str() {
             "-h" | "--help" => {
                 println!("Usage: {} [OPTIONS]", args[0]);
@@ -290,9 +622,11 @@ pub fn main() {
                     amt_pend
                 );
                 println!("  -n\t\t\t\tAlias for -p.");
-                println!("  -v, --vary\t\t\tVary the angle, length1, length2, mass1, or mass2. [angle]");
+                println!("  -v, --vary\t\t\tVary the angle, angle1, angle2, velocity1, velocity2, length1, length2, mass1, mass2 or gravity (from 0 up to the -g value). [angle]");
+                println!("  --vary2\t\t\tSecond parameter to vary, down the rows of the grid. [none]");
+                println!("  --grid\t\t\tSweep over a WxH grid, -v along the columns and --vary2 down the rows, replacing -p.");
                 println!(
-                    "  -s, --separation\t\tSeparation between pendulums. [{}] (used only when varying angle)",
+                    "  -s, --separation\t\tSeparation between pendulums. [{}] (used when varying an angle or angular velocity)",
                     amt_sep
                 );
                 println!(
@@ -326,9 +660,49 @@ pub fn main() {
                     "  -pw, --width\t\t\tLine width of pendulums. [{}]\n",
                     settings.pend_width
                 );
-                println!(" -speed\t\t\t\tSpeed of the simulation. [{}]", settings.speed);
+                println!(" -speed\t\t\t\tSimulated time per frame (per tick in the window). [{}]", settings.speed);
+                println!("  --substeps\t\t\tPhysics steps per frame, each of speed/substeps. [{}]", settings.substeps);
+                println!("  --tps\t\t\t\tPhysics ticks per second in the window. [{}]", settings.tick_rate);
+                println!("  --threads\t\t\tThreads stepping the ensemble, 0 for one per core. [{}]", settings.threads);
                 println!("  -q, --quality\t\t\tQuality of the pendulums. (1-3) [1]");
+                println!("  --model\t\t\tEquations of motion, exact, legacy (small-angle first term) compound (rigid rods) or elastic (spring arms). Chains with --links other than 2 are always exact. [exact]");
+                println!("  -i, --integrator\t\tIntegrator, euler, semi-implicit, rk4, verlet, yoshida4 or rk45. [euler]");
+                println!("  --rtol\t\t\tRelative tolerance of the rk45 integrator. [{}]", settings.rtol);
+                println!("  --atol\t\t\tAbsolute tolerance of the rk45 integrator. [{}]", settings.atol);
+                println!("  --damping1\t\t\tViscous friction at the pivot. [{}]", settings.damping1);
+                println!("  --damping2\t\t\tViscous friction at the middle joint. [{}]", settings.damping2);
+                println!("  --drag\t\t\tQuadratic air drag on each bob. [{}]", settings.drag);
+                println!("  --stiffness\t\t\tSpring constant of the arms in the elastic model. [{}]", settings.stiffness);
+                println!("  --spring-damping\t\tDamping of the arm springs in the elastic model. [{}]", settings.spring_damping);
+                println!("  --drive\t\t\tPivot motion, fixed, horizontal or vertical. [fixed]");
+                println!("  --drive-amp\t\t\tAmplitude of the pivot oscillation. [{}]", settings.drive_amp);
+                println!("  --drive-freq\t\t\tAngular frequency of the pivot oscillation. [{}]", settings.drive_freq);
+                println!("  --a1\t\t\t\tInitial angle of arm 1 from straight down, in degrees or with a rad suffix. [{}]", settings.a1.to_degrees());
+                println!("  --a2\t\t\t\tInitial angle of arm 2 from straight down. [{}]", settings.a2.to_degrees());
+                println!("  --a1-v\t\t\tInitial angular velocity of arm 1, in degrees per unit time or with a rad suffix. [{}]", settings.a1_v.to_degrees());
+                println!("  --a2-v\t\t\tInitial angular velocity of arm 2. [{}]", settings.a2_v.to_degrees());
+                println!("  --links\t\t\tNumber of links in each pendulum, 2 is the double pendulum. [{}]", links);
+                println!("  --lengths\t\t\tComma separated link lengths, the first two replace -r1 and -r2.");
+                println!("  --masses\t\t\tComma separated link masses, the first two replace -m1 and -m2.");
+                println!("  --view\t\t\tDraw the pendulums (physical) or each as a point in phase space (phase). [physical]");
+                println!("  --axes\t\t\tAxes of the phase view, two of a1, a2, a1_v and a2_v. Velocities are in units of sqrt(g/r1). [a1,a2]");
                 println!("  -c, --compile\t\t\tCompile the frames into a video, suitable for large amounts of pendulums. [false] ");
+                println!("  --fractal\t\t\tWith -c, draw each pendulum of the grid as a pixel coloured by angle, energy or flip. The map needs a second sweep, e.g. -v angle1 --vary2 angle2. [off]");
+                println!("  --flip-csv\t\t\tWrite each pendulum's cell, swept values and time to first flip to this CSV file.");
+                println!("  --flip-png\t\t\tWrite the times to first flip as an image over the parameter grid.");
+                println!("  --lyapunov-csv\t\tEstimate each pendulum's largest Lyapunov exponent with a shadow pendulum and write it to this CSV file.");
+                println!("  --poincare-png\t\tRecord every pendulum's Poincare section and draw it to this PNG file.");
+                println!("  --section\t\t\tArm whose upward passes through straight down make the section, a1 or a2. [a2]");
+                println!("  --bench\t\t\tTime this many ticks of the pendulums one by one against the struct of arrays ensemble, which steps them whenever the model, friction and integrator allow, then exit.");
+                println!("  --stream\t\t\tWith -c, pipe the frames straight into ffmpeg instead of saving them as PNGs. [false]");
+                println!("  --fps\t\t\t\tFrames per second the compiled frames are played at. [10]");
+                println!("  --video-fps\t\t\tFrames per second of the video ffmpeg writes. [60]");
+                println!("  --codec\t\t\tffmpeg video codec. [libx264]");
+                println!("  --crf\t\t\t\tConstant rate factor, lower is better quality. [codec default]");
+                println!("  --pix-fmt\t\t\tPixel format of the video. [yuv420p]");
+                println!("  -o, --output\t\t\tVideo file to write. [output.mp4]");
+                println!("  --format\t\t\tContainer to write, otherwise guessed from the output's extension.");
+                println!("  -- ARGS\t\t\tPass the remaining arguments to ffmpeg, before the output file.");
                 println!("  -f, --frames\t\t\tNumber of frames to compile. [50]");
 
                 return;
@@ -337,14 +711,14 @@ pub fn main() {
                 amt_pend = args[i + 1].parse().unwrap();
             }
             "-v" | "--vary" => {
-                settings.varying = match args[i + 1].as_str() {
-                    "angle" => VaryingType::Angle,
-                    "length1" => VaryingType::Length1,
-                    "length2" => VaryingType::Length2,
-                    "mass1" => VaryingType::Mass1,
-                    "mass2" => VaryingType::Mass2,
-                    _ => VaryingType::Angle,
-                };
+                settings.varying = parse_varying(&args[i + 1]);
+            }
+            "--vary2" => {
+                settings.varying2 = Some(parse_varying(&args[i + 1]));
+            }
+            "--grid" => {
+                let (w, h) = args[i + 1].split_once('x').unwrap();
+                grid = Some((w.parse().unwrap(), h.parse().unwrap()));
             }
             "-s" | "--separation" => {
                 amt_sep = args[i + 1].parse().unwrap();
@@ -376,6 +750,15 @@ pub fn main() {
             "-speed" => {
                 settings.speed = args[i + 1].parse().unwrap();
             }
+            "--substeps" => {
+                settings.substeps = args[i + 1].parse().unwrap();
+            }
+            "--tps" => {
+                settings.tick_rate = args[i + 1].parse().unwrap();
+            }
+            "--threads" => {
+                settings.threads = args[i + 1].parse().unwrap();
+            }
             "-q" | "--quality" => {
                 settings.quality = match args[i + 1].parse().unwrap() {
                     1 => Quality::Low,
@@ -384,115 +767,242 @@ pub fn main() {
                     _ => Quality::Low,
                 };
             }
+            "--model" => {
+                settings.model = match args[i + 1].as_str() {
+                    "exact" => Model::Exact,
+                    "legacy" => Model::Legacy,
+                    "compound" => Model::Compound,
+                    "elastic" => Model::Elastic,
+                    _ => Model::Exact,
+                };
+            }
+            "-i" | "--integrator" => {
+                settings.integrator = match args[i + 1].as_str() {
+                    "euler" => Integrator::Euler,
+                    "semi-implicit" => Integrator::SemiImplicitEuler,
+                    "rk4" => Integrator::Rk4,
+                    "verlet" => Integrator::Verlet,
+                    "yoshida4" => Integrator::Yoshida4,
+                    "rk45" => Integrator::Rk45,
+                    _ => Integrator::Euler,
+                };
+            }
+            "--rtol" => {
+                settings.rtol = args[i + 1].parse().unwrap();
+            }
+            "--atol" => {
+                settings.atol = args[i + 1].parse().unwrap();
+            }
+            "--damping1" => {
+                settings.damping1 = args[i + 1].parse().unwrap();
+            }
+            "--damping2" => {
+                settings.damping2 = args[i + 1].parse().unwrap();
+            }
+            "--drag" => {
+                settings.drag = args[i + 1].parse().unwrap();
+            }
+            "--stiffness" => {
+                settings.stiffness = args[i + 1].parse().unwrap();
+            }
+            "--spring-damping" => {
+                settings.spring_damping = args[i + 1].parse().unwrap();
+            }
+            "--drive" => {
+                settings.drive = match args[i + 1].as_str() {
+                    "fixed" => Drive::Fixed,
+                    "horizontal" => Drive::Horizontal,
+                    "vertical" => Drive::Vertical,
+                    _ => Drive::Fixed,
+                };
+            }
+            "--drive-amp" => {
+                settings.drive_amp = args[i + 1].parse().unwrap();
+            }
+            "--drive-freq" => {
+                settings.drive_freq = args[i + 1].parse().unwrap();
+            }
+            "--a1" => {
+                settings.a1 = parse_angle(&args[i + 1]);
+            }
+            "--a2" => {
+                settings.a2 = parse_angle(&args[i + 1]);
+            }
+            "--a1-v" => {
+                settings.a1_v = parse_angle(&args[i + 1]);
+            }
+            "--a2-v" => {
+                settings.a2_v = parse_angle(&args[i + 1]);
+            }
+            "--links" => {
+                links = args[i + 1].parse::<usize>().unwrap().max(1);
+            }
+            "--lengths" => {
+                link_lengths = args[i + 1].split(',').map(|l| l.parse().unwrap()).collect();
+            }
+            "--masses" => {
+                link_masses = args[i + 1].split(',').map(|m| m.parse().unwrap()).collect();
+            }
             "-c" | "--compile" => {
                 compile = true;
+                skip = false;
+            }
+            "--stream" => {
+                video.stream = true;
+                skip = false;
+            }
+            "--fps" => {
+                video.input_fps = args[i + 1].parse().unwrap();
+            }
+            "--video-fps" => {
+                video.output_fps = args[i + 1].parse().unwrap();
+            }
+            "--codec" => {
+                video.codec = args[i + 1].clone();
+            }
+            "--crf" => {
+                video.crf = Some(args[i + 1].parse().unwrap());
+            }
+            "--pix-fmt" => {
+                video.pix_fmt = args[i + 1].clone();
+            }
+            "-o" | "--output" => {
+                video.output = args[i + 1].clone();
+            }
+            "--format" => {
+                video.format = Some(args[i + 1].clone());
+            }
+            "--fractal" => {
+                settings.fractal = match args[i + 1].as_str() {
+                    "angle" => Some(FractalColor::Angle),
+                    "energy" => Some(FractalColor::Energy),
+                    "flip" => Some(FractalColor::Flipped),
+                    _ => Some(FractalColor::Angle),
+                };
+            }
+            "--flip-csv" => {
+                exports.flip_csv = Some(args[i + 1].clone());
+            }
+            "--flip-png" => {
+                exports.flip_png = Some(args[i + 1].clone());
+            }
+            "--lyapunov-csv" => {
+                exports.lyapunov_csv = Some(args[i + 1].clone());
+            }
+            "--poincare-png" => {
+                exports.poincare_png = Some(args[i + 1].clone());
+            }
+            "--section" => {
+                exports.section = match args[i + 1].as_str() {
+                    "a1" => Section::A1,
+                    "a2" => Section::A2,
+                    _ => Section::A2,
+                };
+            }
+            "--view" => {
+                settings.view = match args[i + 1].as_str() {
+                    "physical" => View::Physical,
+                    "phase" => View::Phase,
+                    _ => View::Physical,
+                };
+            }
+            "--axes" => {
+                let (x, y) = args[i + 1].split_once(',').unwrap();
+                settings.phase_axes = (parse_phase_axis(x), parse_phase_axis(y));
+            }
+            "--bench" => {
+                bench = Some(args[i + 1].parse().unwrap());
             }
             "-f" | "--frames" => {
                 compile_frames = args[i + 1].parse().unwrap();
             }
             _ => {
-                // If the argument is not found print a message (will log on numbers too tho).
+                // If the argument is not found print a message.
                 if args[i].contains("-") {
                     println!("Unknown argument: {}", args[i]);
                 }
+                skip = false;
             }
         }
     }
 
-    // If the compile flag is set, compile the frames and exit.
-
+    if settings.threads == 0 {
+        settings.threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    }
 
+    // The first two links are the double pendulum's arms, so the sweeps keep working on them.
+    let link_lengths = link_values(&link_lengths, settings.r1, settings.r2, links);
+    let link_masses = link_values(&link_masses, settings.max_m1, settings.max_m2, links);
+    settings.r1 = link_lengths[0];
+    settings.max_m1 = link_masses[0];
+    if links > 1 {
+        settings.r2 = link_lengths[1];
+        settings.max_m2 = link_masses[1];
+    }
 
+    // Lay the pendulums out on a grid. A second sweep without an explicit grid gets a square one,
+    // and so does the fractal map, which would otherwise be a single row of pixels. The map's side
+    // is kept even, which ffmpeg's usual pixel formats need.
+    let mut side = (amt_pend as f64).sqrt() as usize;
+    if settings.fractal.is_some() {
+        side = (side - side % 2).max(2);
+    }
+    settings.grid = match (grid, settings.varying2) {
+        (Some(grid), _) => grid,
+        (None, Some(_)) => (side, side),
+        (None, None) if settings.fractal.is_some() => {
+            println!("The fractal map needs a second sweep, e.g. -v angle1 --vary2 angle2, so every row of this {}x{} one is the same.", side, side);
+            (side, side)
+        }
+        (None, None) => (amt_pend, 1),
+    };
+    let (columns, rows) = settings.grid;
+    amt_pend = columns * rows;
 
     // Innit the pendulums
     let mut pends = Vec::new();
     for i in 0..amt_pend {
-        match settings.varying {
-            VaryingType::Angle => {
-                pends.push(Pendulum::new(
-                    -2.0 + (i as f64 * (amt_sep / amt_pend as f64)),
-                    -2.0 + (i as f64 * (amt_sep / amt_pend as f64)),
-                    settings.r1,
-                    settings.r2,
-                    settings.max_m1,
-                    settings.max_m2,
-                    Hsl::from(
-                        i as f32 * (360.0 / amt_pend as f32),
-                        100.0f32,
-                        50.0f32,
-                    )
-                        .to_rgb(),
-                ));
-            }
-            VaryingType::Length1 => {
-                pends.push(Pendulum::new(
-                    -2.0,
-                    -2.0,
-                    settings.r1 + (i as f64 * (settings.max_m1 / amt_pend as f64)),
-                    settings.r2,
-                    settings.max_m1,
-                    settings.max_m2,
-                    Hsl::from(
-                        i as f32 * (360.0 / amt_pend as f32),
-                        100.0f32,
-                        50.0f32,
-                    )
-                        .to_rgb(),
-                ));
-            }
-            VaryingType::Length2 => {
-                pends.push(Pendulum::new(
-                    -2.0,
-                    -2.0,
-                    settings.r1,
-                    settings.r2 + (i as f64 * (settings.max_m2 / amt_pend as f64)),
-                    settings.max_m1,
-                    settings.max_m2,
-                    Hsl::from(
-                        i as f32 * (360.0 / amt_pend as f32),
-                        100.0f32,
-                        50.0f32,
-                    )
-                        .to_rgb(),
-                ));
-            }
-            VaryingType::Mass1 => {
-                pends.push(Pendulum::new(
-                    -2.0,
-                    -2.0,
-                    settings.r1,
-                    settings.r2,
-                    i as f64 * (settings.max_m1 / amt_pend as f64),
-                    settings.max_m2,
-                    Hsl::from(
-                        i as f32 * (360.0 / amt_pend as f32),
-                        100.0f32,
-                        50.0f32,
-                    )
-                        .to_rgb(),
-                ));
-            }
-            VaryingType::Mass2 => {
-                pends.push(Pendulum::new(
-                    -2.0,
-                    -2.0,
-                    settings.r1,
-                    settings.r2,
-                    settings.max_m1,
-                    i as f64 * (settings.max_m2 / amt_pend as f64),
-                    Hsl::from(
-                        i as f32 * (360.0 / amt_pend as f32),
-                        100.0f32,
-                        50.0f32,
-                    )
-                        .to_rgb(),
-                ));
-            }
+        let (column, row) = (i % columns, i / columns);
+        let mut pend = Pendulum::new(
+            [settings.a1, settings.a2],
+            [settings.a1_v, settings.a2_v],
+            [settings.r1, settings.r2],
+            [settings.max_m1, settings.max_m2],
+            settings.g,
+            Hsl::from(
+                i as f32 * (360.0 / amt_pend as f32),
+                100.0f32,
+                50.0f32,
+            )
+                .to_rgb(),
+        );
+        pend.sweep[0] = settings.varying.apply(&mut pend, column, columns, amt_sep, &settings);
+        if let Some(varying2) = settings.varying2 {
+            pend.sweep[1] = varying2.apply(&mut pend, row, rows, amt_sep, &settings);
         }
+        pend.cell = (column, row);
+        pends.push(pend);
     }
 
+    let mut chains = Vec::new();
+    if links != 2 && settings.model != Model::Exact {
+        println!("Chains only use the exact model, ignoring --model.");
+        settings.model = Model::Exact;
+    }
+    if links != 2 {
+        chains = pends.iter().map(|p| Chain::from_pendulum(p, &link_lengths, &link_masses)).collect();
+        pends.clear();
+    }
+
+    if let Some(ticks) = bench {
+        ensemble::bench(&pends, &settings, ticks);
+        return;
+    }
+
+    let mut analysis = Analysis::new(exports, &pends, &chains, &settings);
     if compile {
-        compile_pngs::main(compile_frames, pends, amt_sep, settings);
+        compile_pngs::main(compile_frames, &mut pends, &mut chains, settings, &mut analysis, &video);
+        analysis.finish(&pends, &chains, &settings);
         return;
     }
     
@@ -512,12 +1022,19 @@ pub fn main() {
         .unwrap();
 
     // Create and run the app
+    let stepper = Stepper::new(&pends, &settings);
     let mut app = App {
         gl: GlGraphics::new(opengl),
         pends,
+        chains,
         settings,
+        analysis,
+        stepper,
         fps_counter: 0,
         last_update: Instant::now(),
+        last_tick: Instant::now(),
+        accumulator: 0.0,
+        dropped: 0,
     };
 
     // Event Loop
@@ -534,10 +1051,19 @@ pub fn main() {
             app.update(&args);
         }
     }
+    app.stepper.sync(&mut app.pends);
+    simulation::print_step_stats(&app.pends, &app.chains, &app.settings);
+    app.analysis.finish(&app.pends, &app.chains, &app.settings);
 }
-// mod main_processing;
-//
-// fn main() {
-//     main_processing::main();
-// }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_angle_reads_degrees_and_radians() {
+        assert!((parse_angle("-90") + PI / 2.0).abs() < 1e-12);
+        assert!((parse_angle("180deg") - PI).abs() < 1e-12);
+        assert_eq!(parse_angle("0.5rad"), 0.5);
+    }
+}
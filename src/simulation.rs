@@ -0,0 +1,601 @@
+use std::f64::consts::PI;
+
+use colors_transform::Rgb;
+
+use crate::{Drive, Model, Pendulum, PhaseAxis, Settings};
+use crate::chain::Chain;
+use crate::elastic::Elastic;
+use crate::ensemble::Ensemble;
+use crate::integrators::{Dynamics, Hamiltonian, Integrator, StepControl};
+
+// The arms of a double pendulum. Along with the model and gravity they are all its equations of
+// motion need, so `Pendulum` and the struct of arrays ensemble both step through these.
+#[derive(Clone, Copy)]
+pub(crate) struct Arms {
+    pub(crate) r1: f64,
+    pub(crate) r2: f64,
+    pub(crate) m1: f64,
+    pub(crate) m2: f64,
+}
+
+impl Arms {
+    // Angular accelerations of both arms for the state `[a1, a2, a1_v, a2_v]` without friction, in
+    // a field of strength `g` that the angles are measured from.
+    pub(crate) fn accelerations(self, model: Model, g: f64, y: &[f64; 4]) -> (f64, f64) {
+        let [a1, a2, a1_v, a2_v] = *y;
+        let Arms { r1, r2, m1, m2 } = self;
+
+        if model == Model::Compound {
+            // M q'' = f, f1 = -b sin(a1 - a2) a2_v^2 - p1 g sin(a1), f2 = b sin(a1 - a2) a1_v^2 - p2 g sin(a2)
+            let (a, b, c) = self.inertia(model);
+            let (p1, p2) = self.gravity_moments(model);
+            let (sin, cos) = ((a1 - a2).sin(), (a1 - a2).cos());
+            let f1 = -b * sin * a2_v * a2_v - p1 * g * (a1).sin();
+            let f2 = b * sin * a1_v * a1_v - p2 * g * (a2).sin();
+            let det = a * c - b * b * cos * cos;
+            return ((c * f1 - b * cos * f2) / det, (a * f2 - b * cos * f1) / det);
+        }
+
+        let gravity1 = match model {
+            Model::Legacy => a1,
+            _ => (a1).sin(),
+        };
+
+        let mut num1 = -g * (2.0 * m1 + m2) * gravity1;
+        let mut num2 = -m2 * g * (a1 - 2.0 * a2).sin();
+        let mut num3 = -2.0 * (a1 - a2).sin() * m2;
+        let mut num4 = a2_v * a2_v * r2 + a1_v * a1_v * r1 * (a1 - a2).cos();
+        let mut den = r1 * (2.0 * m1 + m2 - m2 * (2.0 * a1 - 2.0 * a2).cos());
+        let a1_a = (num1 + num2 + num3 * num4) / den;
+
+        num1 = 2.0 * (a1 - a2).sin();
+        num2 = a1_v * a1_v * r1 * (m1 + m2);
+        num3 = g * (m1 + m2) * (a1).cos();
+        num4 = a2_v * a2_v * r2 * m2 * (a1 - a2).cos();
+        den = r2 * (2.0 * m1 + m2 - m2 * (2.0 * a1 - 2.0 * a2).cos());
+        let a2_a = (num1 * (num2 + num3 + num4)) / den;
+
+        (a1_a, a2_a)
+    }
+
+    // Mass matrix `[[a, b], [b, c]]` of the arms at angles `a1`, `a2`, as `(a, b, c)`.
+    pub(crate) fn mass_matrix(self, model: Model, a1: f64, a2: f64) -> (f64, f64, f64) {
+        let (a, b, c) = self.inertia(model);
+        (a, b * (a1 - a2).cos(), c)
+    }
+
+    // Constant parts `(a, b, c)` of the mass matrix `[[a, b cos(a1 - a2)], [b cos(a1 - a2), c]]`.
+    // The compound model treats each arm as a uniform rod (moment of inertia m r^2 / 3 about its
+    // end, centre of mass halfway along) instead of a point mass on a massless rod.
+    fn inertia(self, model: Model) -> (f64, f64, f64) {
+        let Arms { r1, r2, m1, m2 } = self;
+        match model {
+            Model::Compound => ((m1 / 3.0 + m2) * r1 * r1, m2 * r1 * r2 / 2.0, m2 * r2 * r2 / 3.0),
+            _ => ((m1 + m2) * r1 * r1, m2 * r1 * r2, m2 * r2 * r2),
+        }
+    }
+
+    // Gravity moments `(p1, p2)` per unit of g, so the potential is -g (p1 cos(a1) + p2 cos(a2)).
+    pub(crate) fn gravity_moments(self, model: Model) -> (f64, f64) {
+        let Arms { r1, r2, m1, m2 } = self;
+        match model {
+            Model::Compound => ((m1 / 2.0 + m2) * r1, m2 * r2 / 2.0),
+            _ => ((m1 + m2) * r1, m2 * r2),
+        }
+    }
+
+    // `[a1, a2, a1_v, a2_v]` -> `[a1, a2, p1, p2]`
+    pub(crate) fn to_momenta(self, model: Model, y: &[f64; 4]) -> [f64; 4] {
+        let [a1, a2, a1_v, a2_v] = *y;
+        let (a, b, c) = self.mass_matrix(model, a1, a2);
+        [a1, a2, a * a1_v + b * a2_v, b * a1_v + c * a2_v]
+    }
+
+    // `[a1, a2, p1, p2]` -> `[a1, a2, a1_v, a2_v]`
+    pub(crate) fn to_velocities(self, model: Model, x: &[f64; 4]) -> [f64; 4] {
+        let [a1, a2, p1, p2] = *x;
+        let (a, b, c) = self.mass_matrix(model, a1, a2);
+        let det = a * c - b * b;
+        [a1, a2, (c * p1 - b * p2) / det, (a * p2 - b * p1) / det]
+    }
+
+    // Rates of change of the momenta for the state `y` and its accelerations, p' = M * q'' + M' * q',
+    // which holds for the non-conservative legacy model too.
+    pub(crate) fn momentum_rates(self, model: Model, y: &[f64; 4], (a1_a, a2_a): (f64, f64)) -> (f64, f64) {
+        let [a1, a2, a1_v, a2_v] = *y;
+        let (a, b, c) = self.mass_matrix(model, a1, a2);
+        let b_v = -self.inertia(model).1 * (a1 - a2).sin() * (a1_v - a2_v);
+        (a * a1_a + b * a2_a + b_v * a2_v, b * a1_a + c * a2_a + b_v * a1_v)
+    }
+}
+
+impl Pendulum {
+    pub(crate) fn arms(&self) -> Arms {
+        Arms { r1: self.r1, r2: self.r2, m1: self.m1, m2: self.m2 }
+    }
+
+    // Angular accelerations of both arms for the state `[a1, a2, a1_v, a2_v]` at time `t`.
+    fn accelerations(&self, settings: &Settings, t: f64, y: &[f64; 4]) -> (f64, f64) {
+        let [a1, a2, a1_v, a2_v] = *y;
+
+        // A moving pivot adds a pseudo-force, so the arms feel a uniform field `g` tilted from
+        // straight down. The equations then use angles measured from that field.
+        let (g, tilt) = effective_gravity(settings, t);
+        let (a1, a2) = (a1 - tilt, a2 - tilt);
+        let (a1_a, a2_a) = self.arms().accelerations(settings.model, g, &[a1, a2, a1_v, a2_v]);
+
+        if settings.damping1 == 0.0 && settings.damping2 == 0.0 && settings.drag == 0.0 {
+            return (a1_a, a2_a);
+        }
+
+        // Add the response to the friction and drag forces, M^-1 * Q.
+        let (q1, q2) = self.friction_forces(settings, t, y);
+        let (a, b, c) = self.arms().mass_matrix(settings.model, a1, a2);
+        let det = a * c - b * b;
+        (a1_a + (c * q1 - b * q2) / det, a2_a + (a * q2 - b * q1) / det)
+    }
+
+    // Generalised forces from viscous friction at the joints and quadratic air drag on the bobs.
+    fn friction_forces(&self, settings: &Settings, t: f64, y: &[f64; 4]) -> (f64, f64) {
+        let [a1, a2, a1_v, a2_v] = *y;
+
+        // The second joint resists the relative motion of the two arms.
+        let relative = a2_v - a1_v;
+        let mut q1 = -settings.damping1 * a1_v + settings.damping2 * relative;
+        let mut q2 = -settings.damping2 * relative;
+
+        if settings.drag != 0.0 {
+            // Directions the bobs move in per unit of each angle.
+            let (d1x, d1y) = (self.r1 * (a1).cos(), -self.r1 * (a1).sin());
+            let (d2x, d2y) = (self.r2 * (a2).cos(), -self.r2 * (a2).sin());
+
+            // Drag acts on the velocity through the air, which includes the pivot's.
+            let (_, (px_v, py_v), _) = pivot_motion(settings, t);
+            let (v1x, v1y) = (px_v + d1x * a1_v, py_v + d1y * a1_v);
+            let (v2x, v2y) = (v1x + d2x * a2_v, v1y + d2y * a2_v);
+
+            let s1 = -settings.drag * v1x.hypot(v1y);
+            let s2 = -settings.drag * v2x.hypot(v2y);
+            let (f1x, f1y) = (s1 * v1x, s1 * v1y);
+            let (f2x, f2y) = (s2 * v2x, s2 * v2y);
+
+            q1 += (f1x + f2x) * d1x + (f1y + f2y) * d1y;
+            q2 += f2x * d2x + f2y * d2y;
+        }
+
+        (q1, q2)
+    }
+
+    // Current position of the pivot, before magnification.
+    pub(crate) fn pivot(&self, settings: &Settings) -> (f64, f64) {
+        pivot_motion(settings, self.t).0
+    }
+
+    // `[a1, a2, a1_v, a2_v]`
+    fn state(&self) -> [f64; 4] {
+        [self.a1, self.a2, self.a1_v, self.a2_v]
+    }
+
+    // Positions of the two bobs relative to the pivot, before magnification. These use the
+    // current arm lengths, which only differ from `r1`/`r2` in the elastic model.
+    pub(crate) fn positions(&self) -> [(f64, f64); 2] {
+        let x1 = self.l1 * (self.a1).sin();
+        let y1 = self.l1 * (self.a1).cos();
+
+        let x2 = x1 + self.l2 * (self.a2).sin();
+        let y2 = y1 + self.l2 * (self.a2).cos();
+
+        [(x1, y1), (x2, y2)]
+    }
+}
+
+// What stepping, the statistics, drawing and the analysis need from a double pendulum or a chain,
+// so that both take the same path through them.
+pub(crate) trait Swinging {
+    // Advance by one tick, `settings.speed` of simulated time split into `settings.substeps` steps
+    // of the selected integrator.
+    fn step(&mut self, settings: &Settings);
+    // Kinetic and potential energy.
+    fn energies(&self, settings: &Settings) -> (f64, f64);
+    // Depth of the potential well below the zero of `energies`.
+    fn potential_depth(&self, settings: &Settings) -> f64;
+    // Total energy before the first step.
+    fn initial_energy(&self) -> Option<f64>;
+    fn control(&self) -> StepControl;
+    fn color(&self) -> Rgb;
+    // Cell in the parameter grid and the swept values there.
+    fn cell(&self) -> (usize, usize);
+    fn sweep(&self) -> [f64; 2];
+    // Simulated time the outermost arm first went over the top.
+    fn flip_time(&self) -> Option<f64>;
+    // `[a1, a2, a1_v, a2_v]` of the first two arms, or `None` with only one.
+    fn first_arms(&self) -> Option<[f64; 4]>;
+    fn outer_angle(&self) -> f64;
+    // Position in the phase view, see `phase_point`.
+    fn phase_point(&self, settings: &Settings) -> (f64, f64);
+
+    fn total_energy(&self, settings: &Settings) -> f64 {
+        let (kinetic, potential) = self.energies(settings);
+        kinetic + potential
+    }
+
+    // Change in total energy since the first step. It's relative to the initial energy, or to the
+    // potential depth when that is larger, since a start with both arms horizontal and at rest
+    // has zero energy. Without gravity or motion there is nothing to compare against.
+    fn energy_drift(&self, settings: &Settings) -> Option<f64> {
+        let e0 = self.initial_energy()?;
+        let scale = e0.abs().max(self.potential_depth(settings));
+        if scale == 0.0 {
+            return None;
+        }
+        Some((self.total_energy(settings) - e0) / scale)
+    }
+}
+
+// Every pendulum, then every chain.
+pub(crate) fn swinging<'a>(pends: &'a [Pendulum], chains: &'a [Chain]) -> impl Iterator<Item = &'a dyn Swinging> {
+    let pends = pends.iter().map(|p| p as &dyn Swinging);
+    pends.chain(chains.iter().map(|c| c as &dyn Swinging))
+}
+
+impl Swinging for Pendulum {
+    fn step(&mut self, settings: &Settings) {
+        let settings = &settings.with_gravity(self.g);
+        if self.e0.is_none() {
+            self.e0 = Some(self.total_energy(settings));
+        }
+        let span = settings.speed / settings.substeps.max(1) as f64;
+        for _ in 0..settings.substeps.max(1) {
+            let mut control = self.control;
+            let a2 = self.a2;
+            if settings.model == Model::Elastic {
+                let elastic = Elastic(self);
+                let y = settings.integrator.advance(&elastic, settings, self.t, &elastic.state(), span, &mut control);
+                [self.a1, self.a2, self.l1, self.l2, self.a1_v, self.a2_v, self.l1_v, self.l2_v] = y;
+            } else {
+                let y = settings.integrator.advance(self, settings, self.t, &self.state(), span, &mut control);
+                [self.a1, self.a2, self.a1_v, self.a2_v] = y;
+            }
+            self.control = control;
+            if self.flip_time.is_none() {
+                self.flip_time = flip_time(a2, self.a2, self.t, span);
+            }
+            self.t += span;
+        }
+    }
+
+    // Kinetic and potential energy. The potential is zero with both arms horizontal (y points down).
+    fn energies(&self, settings: &Settings) -> (f64, f64) {
+        let settings = &settings.with_gravity(self.g);
+        if settings.model == Model::Elastic {
+            return Elastic(self).energies(settings);
+        }
+        let (a, b, c) = self.arms().mass_matrix(settings.model, self.a1, self.a2);
+        let kinetic = 0.5 * (a * self.a1_v * self.a1_v + 2.0 * b * self.a1_v * self.a2_v + c * self.a2_v * self.a2_v);
+        let (p1, p2) = self.arms().gravity_moments(settings.model);
+        let potential = -settings.g * (p1 * (self.a1).cos() + p2 * (self.a2).cos());
+        (kinetic, potential)
+    }
+
+    // Depth g (p1 + p2) of the potential well below the zero of `energies`.
+    fn potential_depth(&self, settings: &Settings) -> f64 {
+        let (p1, p2) = self.arms().gravity_moments(settings.model);
+        self.g * (p1 + p2)
+    }
+
+    fn initial_energy(&self) -> Option<f64> {
+        self.e0
+    }
+
+    fn control(&self) -> StepControl {
+        self.control
+    }
+
+    fn color(&self) -> Rgb {
+        self.color
+    }
+
+    fn cell(&self) -> (usize, usize) {
+        self.cell
+    }
+
+    fn sweep(&self) -> [f64; 2] {
+        self.sweep
+    }
+
+    fn flip_time(&self) -> Option<f64> {
+        self.flip_time
+    }
+
+    fn first_arms(&self) -> Option<[f64; 4]> {
+        Some(self.state())
+    }
+
+    fn outer_angle(&self) -> f64 {
+        self.a2
+    }
+
+    fn phase_point(&self, settings: &Settings) -> (f64, f64) {
+        phase_point(settings, [self.a1, self.a2], [self.a1_v, self.a2_v])
+    }
+}
+
+// An angle wrapped into `[-PI, PI)`.
+pub(crate) fn wrap_angle(a: f64) -> f64 {
+    (a + PI).rem_euclid(2.0 * PI) - PI
+}
+
+// Where the first two arms' angles and angular velocities put a pendulum in the phase view, before
+// magnification and with y up. Angles are wrapped and velocities measured in units of
+// sqrt(g / r1), and PI of either spans the length of both arms.
+pub(crate) fn phase_point(settings: &Settings, a: [f64; 2], a_v: [f64; 2]) -> (f64, f64) {
+    let reach = (settings.r1 + settings.r2) / PI;
+    let w0 = match (settings.g / settings.r1).sqrt() {
+        w0 if w0 > 0.0 => w0,
+        _ => 1.0,
+    };
+    let coordinate = |axis| match axis {
+        PhaseAxis::Angle1 => wrap_angle(a[0]) * reach,
+        PhaseAxis::Angle2 => wrap_angle(a[1]) * reach,
+        PhaseAxis::Velocity1 => a_v[0] / w0 * reach,
+        PhaseAxis::Velocity2 => a_v[1] / w0 * reach,
+    };
+    let (x_axis, y_axis) = settings.phase_axes;
+    (coordinate(x_axis), -coordinate(y_axis))
+}
+
+// Pivot position, velocity and acceleration at time `t`.
+pub(crate) fn pivot_motion(settings: &Settings, t: f64) -> ((f64, f64), (f64, f64), (f64, f64)) {
+    let w = settings.drive_freq;
+    let offset = settings.drive_amp * (w * t).cos();
+    let speed = -settings.drive_amp * w * (w * t).sin();
+    let accel = -w * w * offset;
+    match settings.drive {
+        Drive::Fixed => ((0.0, 0.0), (0.0, 0.0), (0.0, 0.0)),
+        Drive::Horizontal => ((offset, 0.0), (speed, 0.0), (accel, 0.0)),
+        Drive::Vertical => ((0.0, offset), (0.0, speed), (0.0, accel)),
+    }
+}
+
+// Strength of gravity plus the pivot's pseudo-force at time `t`, and its tilt from straight down.
+pub(crate) fn effective_gravity(settings: &Settings, t: f64) -> (f64, f64) {
+    if settings.drive == Drive::Fixed {
+        return (settings.g, 0.0);
+    }
+    tilted_gravity(settings.g, pivot_motion(settings, t).2)
+}
+
+// Strength and tilt of gravity `g` as felt from a pivot accelerating at `(ax, ay)`.
+pub(crate) fn tilted_gravity(g: f64, (ax, ay): (f64, f64)) -> (f64, f64) {
+    let (gx, gy) = (-ax, g - ay);
+    (gx.hypot(gy), gx.atan2(gy))
+}
+
+// When an arm going from angle `before` to `after` in the step from `t` to `t + span` first passes
+// straight up, interpolating linearly within the step. An arm that starts past the top doesn't
+// count as flipping.
+pub(crate) fn flip_time(before: f64, after: f64, t: f64, span: f64) -> Option<f64> {
+    if before.abs() > PI || after.abs() <= PI {
+        return None;
+    }
+    let top = PI.copysign(after);
+    Some(t + span * (top - before) / (after - before))
+}
+
+// Advance the whole ensemble by one tick, one pendulum at a time. Both the window and the PNG
+// compiler step through here or an `Ensemble` (see `Stepper`), so a frame index maps to the same
+// simulated time in either. The pendulums are independent, so
+// they are split into one contiguous share per thread and the result doesn't depend on the count.
+pub(crate) fn tick(pends: &mut [Pendulum], chains: &mut [Chain], settings: &Settings) {
+    step_shares(pends, settings);
+    step_shares(chains, settings);
+}
+
+fn step_shares<T: Swinging + Send>(items: &mut [T], settings: &Settings) {
+    if settings.threads <= 1 {
+        for item in items.iter_mut() {
+            item.step(settings);
+        }
+        return;
+    }
+
+    std::thread::scope(|scope| {
+        for share in items.chunks_mut(items.len().div_ceil(settings.threads).max(1)) {
+            scope.spawn(move || {
+                for item in share {
+                    item.step(settings);
+                }
+            });
+        }
+    });
+}
+
+// Steps the pendulums and chains a tick at a time, the pendulums through a struct of arrays
+// `Ensemble` when the settings allow it since that is faster for large grids. The ensemble keeps
+// the pendulums' states between ticks, so `sync` has to bring them up to date before they are
+// drawn or analysed, and `reload` takes in any changes made to them.
+//
+// The symplectic integrators iterate a whole batch until its slowest lane converges, so their
+// results can differ from stepping each pendulum on its own in the last bit.
+pub(crate) struct Stepper {
+    ensemble: Option<Ensemble>,
+    // Whether the ensemble has stepped since the pendulums were last brought up to date.
+    stale: bool,
+    // Whether the pendulums were changed since the ensemble last loaded them.
+    changed: bool,
+}
+
+impl Stepper {
+    pub(crate) fn new(pends: &[Pendulum], settings: &Settings) -> Stepper {
+        let ensemble = (Ensemble::supports(settings) && !pends.is_empty()).then(|| Ensemble::from_pendulums(pends));
+        Stepper { ensemble, stale: false, changed: true }
+    }
+
+    pub(crate) fn tick(&mut self, pends: &mut [Pendulum], chains: &mut [Chain], settings: &Settings) {
+        let Some(ensemble) = &mut self.ensemble else {
+            tick(pends, chains, settings);
+            return;
+        };
+        if self.changed {
+            for pend in pends.iter_mut().filter(|p| p.e0.is_none()) {
+                pend.e0 = Some(pend.total_energy(settings));
+            }
+            ensemble.load(pends);
+            self.changed = false;
+        }
+        ensemble.tick(settings);
+        self.stale = true;
+        tick(&mut [], chains, settings);
+    }
+
+    // Bring `pends` up to date with the ensemble.
+    pub(crate) fn sync(&mut self, pends: &mut [Pendulum]) {
+        if let Some(ensemble) = &mut self.ensemble {
+            if self.stale {
+                ensemble.store(pends);
+                self.stale = false;
+            }
+        }
+    }
+
+    // Step on from `pends` as they are now, after they were changed since the last `sync`.
+    pub(crate) fn reload(&mut self) {
+        self.changed = true;
+    }
+}
+
+// Min, mean and max absolute energy drift across the ensemble, once it has been stepped. Pendulums
+// whose state has blown up to NaN or infinity are left out and counted instead.
+pub(crate) fn energy_drift_summary(pends: &[Pendulum], chains: &[Chain], settings: &Settings) -> Option<String> {
+    let (mut count, mut diverged) = (0, 0);
+    let (mut min, mut sum, mut max) = (f64::INFINITY, 0.0, 0.0f64);
+    for drift in swinging(pends, chains).filter_map(|s| s.energy_drift(settings)) {
+        if !drift.is_finite() {
+            diverged += 1;
+            continue;
+        }
+        let drift = drift.abs();
+        min = min.min(drift);
+        max = max.max(drift);
+        sum += drift;
+        count += 1;
+    }
+    let summary = (count > 0).then(|| format!("min {:.2e}, mean {:.2e}, max {:.2e}", min, sum / count as f64, max));
+    match (summary, diverged) {
+        (summary, 0) => summary,
+        (Some(summary), _) => Some(format!("{}, {} not finite", summary, diverged)),
+        (None, _) => Some(format!("{} not finite", diverged)),
+    }
+}
+
+// Print the accepted/rejected step counts of a run.
+pub(crate) fn print_step_stats(pends: &[Pendulum], chains: &[Chain], settings: &Settings) {
+    if settings.integrator != Integrator::Rk45 || (pends.is_empty() && chains.is_empty()) {
+        return;
+    }
+    let controls: Vec<StepControl> = swinging(pends, chains).map(|s| s.control()).collect();
+    let accepted: u64 = controls.iter().map(|c| c.accepted).sum();
+    let rejected: u64 = controls.iter().map(|c| c.rejected).sum();
+    let max_rejected = controls.iter().map(|c| c.rejected).max().unwrap_or(0);
+    println!(
+        "Steps: {} accepted, {} rejected ({:.2}%), at most {} rejected for one pendulum",
+        accepted,
+        rejected,
+        100.0 * rejected as f64 / (accepted + rejected).max(1) as f64,
+        max_rejected
+    );
+}
+
+impl Dynamics for Pendulum {
+    type State = [f64; 4];
+
+    fn derivative(&self, settings: &Settings, t: f64, y: &[f64; 4]) -> [f64; 4] {
+        let (a1_a, a2_a) = self.accelerations(settings, t, y);
+        [y[2], y[3], a1_a, a2_a]
+    }
+}
+
+impl Hamiltonian for Pendulum {
+    fn to_momenta(&self, settings: &Settings, y: &[f64; 4]) -> [f64; 4] {
+        self.arms().to_momenta(settings.model, y)
+    }
+
+    fn to_velocities(&self, settings: &Settings, x: &[f64; 4]) -> [f64; 4] {
+        self.arms().to_velocities(settings.model, x)
+    }
+
+    fn canonical_derivative(&self, settings: &Settings, t: f64, x: &[f64; 4]) -> [f64; 4] {
+        let y = self.to_velocities(settings, x);
+        let (p1_v, p2_v) = self.arms().momentum_rates(settings.model, &y, self.accelerations(settings, t, &y));
+        [y[2], y[3], p1_v, p2_v]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use colors_transform::Rgb;
+
+    use super::*;
+
+    // Largest energy drift of a double pendulum over `steps` steps of `integrator`.
+    fn worst_drift(integrator: Integrator, steps: usize) -> f64 {
+        let mut settings = Settings::new();
+        settings.integrator = integrator;
+        let r = [settings.r1, settings.r2];
+        let m = [settings.max_m1, settings.max_m2];
+        let mut pend = Pendulum::new([1.0, 2.0], [0.0, 0.0], r, m, settings.g, Rgb::from(0.0, 0.0, 0.0));
+        let mut worst: f64 = 0.0;
+        for _ in 0..steps {
+            pend.step(&settings);
+            worst = worst.max(pend.energy_drift(&settings).unwrap().abs());
+        }
+        worst
+    }
+
+    #[test]
+    fn symplectic_integrators_keep_energy_drift_bounded() {
+        assert!(worst_drift(Integrator::Verlet, 100_000) < 1e-2);
+        assert!(worst_drift(Integrator::Yoshida4, 100_000) < 1e-3);
+    }
+
+    #[test]
+    fn rk45_ticks_the_same_on_any_number_of_threads() {
+        let run = |threads| {
+            let mut settings = Settings::new();
+            settings.integrator = Integrator::Rk45;
+            settings.threads = threads;
+            let r = [settings.r1, settings.r2];
+            let m = [settings.max_m1, settings.max_m2];
+            let mut pends: Vec<Pendulum> = (0..7)
+                .map(|i| Pendulum::new([1.0 + 0.1 * i as f64, 2.0], [0.0, 0.0], r, m, settings.g, Rgb::from(0.0, 0.0, 0.0)))
+                .collect();
+            let mut chains: Vec<Chain> = (0..5)
+                .map(|i| Chain::new(vec![100.0; 3], vec![10.0; 3], vec![1.0 + 0.1 * i as f64, 2.0, 2.5], settings.g, Rgb::from(0.0, 0.0, 0.0)))
+                .collect();
+            for _ in 0..20 {
+                tick(&mut pends, &mut chains, &settings);
+            }
+            let pends: Vec<[f64; 4]> = pends.iter().map(|p| p.state()).collect();
+            let chains: Vec<Vec<f64>> = chains.iter().map(|c| [c.angles.as_slice(), c.velocities.as_slice()].concat()).collect();
+            (pends, chains)
+        };
+        assert_eq!(run(1), run(3));
+    }
+
+    #[test]
+    fn flip_time_interpolates_the_crossing() {
+        let t = flip_time(3.0, 3.3, 1.0, 0.5).unwrap();
+        assert!((t - (1.0 + 0.5 * (PI - 3.0) / 0.3)).abs() < 1e-12);
+        let t = flip_time(-3.1, -3.2, 0.0, 1.0).unwrap();
+        assert!((t - (PI - 3.1) / 0.1).abs() < 1e-12);
+        assert_eq!(flip_time(3.0, 3.1, 0.0, 1.0), None);
+        assert_eq!(flip_time(3.2, 3.3, 0.0, 1.0), None);
+    }
+
+    #[test]
+    fn wrap_angle_stays_within_half_a_turn() {
+        assert!((wrap_angle(1.5 * PI) + 0.5 * PI).abs() < 1e-12);
+        assert!((wrap_angle(-2.5 * PI) + 0.5 * PI).abs() < 1e-12);
+        assert_eq!(wrap_angle(0.25), 0.25);
+    }
+}